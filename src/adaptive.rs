@@ -0,0 +1,166 @@
+//! Adaptive refinement of the probe grid
+
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+
+use crate::grid::Point;
+use crate::Freqs;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn freqs(harm: f64) -> Freqs {
+        Freqs {
+            harms: vec![harm],
+            corrs: vec![harm - 10.0],
+            modes: Vec::new(),
+            geom: Vec::new(),
+            rot_consts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn refine_line() {
+        let points: Vec<_> = [0.0, 0.2, 0.4, 0.6]
+            .into_iter()
+            .map(|z| Point::Cartesian { x: 0.0, y: 0.0, z })
+            .collect();
+        // gradients of 5, 95, and 5 cm⁻¹/Å
+        let fs = [freqs(100.0), freqs(101.0), freqs(120.0), freqs(121.0)];
+        let results: Vec<_> = points.iter().copied().zip(&fs).collect();
+        let got = refine(&results, &points, 50.0);
+        assert_eq!(got.len(), 1);
+        let [x, y, z] = got[0].cartesian();
+        assert_eq!([x, y], [0.0, 0.0]);
+        assert!((z - 0.3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn refine_plane() {
+        // a 2x2 square where the frequency only changes along y, with a
+        // gradient of 50 cm⁻¹/Å
+        let mut points = Vec::new();
+        let mut fs = Vec::new();
+        for z in [0.0, 0.2] {
+            for (y, f) in [(0.0, 100.0), (0.2, 110.0)] {
+                points.push(Point::Cartesian { x: 0.0, y, z });
+                fs.push(freqs(f));
+            }
+        }
+        let results: Vec<_> = points.iter().copied().zip(&fs).collect();
+        let mut got = refine(&results, &points, 25.0);
+        got.sort_by(compare);
+        assert_eq!(got.len(), 2);
+        for (p, want) in got.iter().zip([0.0, 0.2]) {
+            let [_, y, z] = p.cartesian();
+            assert!((y - 0.1).abs() < 1e-12);
+            assert!((z - want).abs() < 1e-12);
+        }
+    }
+}
+
+/// Tolerance for comparing grid coordinates.
+pub(crate) const TOL: f64 = 1e-8;
+
+/// Settings for adaptive refinement of the grid.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Adaptive {
+    /// Add a point halfway between two neighbouring points if the gradient of
+    /// any of their harmonic or corrected frequencies along the coordinate
+    /// separating them is larger than this. The gradient is the difference in
+    /// the frequencies divided by the difference in the coordinate, so it is
+    /// in cm⁻¹/Å along lengths and cm⁻¹/degree along angles.
+    pub(crate) threshold: f64,
+
+    /// The maximum number of refinement passes after the initial grid.
+    pub(crate) levels: usize,
+}
+
+fn values(p: &Point) -> Vec<f64> {
+    p.coords().into_iter().map(|(_, v)| v).collect()
+}
+
+/// Report whether the frequencies of any mode change faster than `threshold`
+/// between `a` and `b`, which are `separation` apart.
+fn differs(a: &Freqs, b: &Freqs, separation: f64, threshold: f64) -> bool {
+    let harms = a.harms.iter().zip(&b.harms);
+    let corrs = a.corrs.iter().zip(&b.corrs);
+    harms
+        .chain(corrs)
+        .any(|(a, b)| (a - b).abs() / separation > threshold)
+}
+
+/// Return the new points to compute in the next refinement pass. Two points in
+/// `results` are neighbours if they differ only in a single coordinate, with
+/// no other point in `results` between them. Points already present in
+/// `existing` are never returned.
+pub(crate) fn refine(
+    results: &[(Point, &Freqs)],
+    existing: &[Point],
+    threshold: f64,
+) -> Vec<Point> {
+    let coords: Vec<_> = results.iter().map(|(p, _)| values(p)).collect();
+    let on_line = |i: usize, j: usize, k: usize| {
+        results[i].0.system() == results[j].0.system()
+            && (0..coords[i].len())
+                .filter(|&l| l != k)
+                .all(|l| (coords[i][l] - coords[j][l]).abs() < TOL)
+    };
+    let close = |a: &Point, b: &Point| {
+        a.system() == b.system()
+            && values(a)
+                .iter()
+                .zip(values(b))
+                .all(|(a, b)| (a - b).abs() < TOL)
+    };
+
+    let mut ret: Vec<Point> = Vec::new();
+    for (i, (point, freqs)) in results.iter().enumerate() {
+        for k in 0..coords[i].len() {
+            // the nearest neighbour above `point` along coordinate `k`
+            let Some(j) = (0..results.len())
+                .filter(|&j| {
+                    coords[j][k] > coords[i][k] + TOL && on_line(i, j, k)
+                })
+                .min_by(|&a, &b| coords[a][k].total_cmp(&coords[b][k]))
+            else {
+                continue;
+            };
+            let separation = coords[j][k] - coords[i][k];
+            if !differs(freqs, results[j].1, separation, threshold) {
+                continue;
+            }
+            let mut mid = coords[i].clone();
+            mid[k] = 0.5 * (coords[i][k] + coords[j][k]);
+            let mid = Point::from_tuple(point.system(), &mid).unwrap();
+            if !ret.iter().chain(existing).any(|p| close(p, &mid)) {
+                ret.push(mid);
+            }
+        }
+    }
+    ret
+}
+
+/// Return the coordinates of `p` from the slowest-varying to the fastest in
+/// the order of the points from [Grid::points].
+///
+/// [Grid::points]: crate::grid::Grid::points
+pub(crate) fn grid_order(p: &Point) -> Vec<f64> {
+    let mut v = values(p);
+    if let Point::Cartesian { .. } = p {
+        v.reverse();
+    }
+    v
+}
+
+/// Order `a` and `b` in the same way as the points from [Grid::points], from
+/// the slowest-varying coordinate to the fastest.
+///
+/// [Grid::points]: crate::grid::Grid::points
+pub(crate) fn compare(a: &Point, b: &Point) -> Ordering {
+    grid_order(a)
+        .partial_cmp(&grid_order(b))
+        .unwrap_or(Ordering::Equal)
+}
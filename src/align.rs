@@ -0,0 +1,219 @@
+//! Rigid superposition of geometries in different orientations
+
+use symm::Atom;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f64; 3], b: [f64; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-8)
+    }
+
+    #[test]
+    fn undo_rotation() {
+        let target = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 2.0, 0.0],
+            [0.3, 0.1, 1.5],
+        ];
+        // rotate by 90° about z, then by 30° about x, and shift
+        let (c, s) = (30f64.to_radians().cos(), 30f64.to_radians().sin());
+        let mobile: Vec<_> = target
+            .iter()
+            .map(|&[x, y, z]| {
+                let [x, y, z] = [-y, x, z];
+                [x + 1.0, c * y - s * z - 2.0, s * y + c * z + 0.5]
+            })
+            .collect();
+        let weights = [1.0; 4];
+        let a = Alignment::new(&mobile, &target, &weights).unwrap();
+        for (m, t) in mobile.iter().zip(&target) {
+            assert!(close(a.point(*m), *t), "{:?} != {t:?}", a.point(*m));
+        }
+        let v = a.vector([0.0, c, s]);
+        assert!(close(v, [1.0, 0.0, 0.0]), "{v:?}");
+        // the two rotations compose to a single one of about 93.8°
+        let want = ((c - 1.0) / 2.0).acos().to_degrees();
+        assert!((a.angle() - want).abs() < 1e-6, "{} != {want}", a.angle());
+    }
+
+    #[test]
+    fn light_atom_fixes_linear() {
+        // a linear host flipped end to end and rotated about its axis, where
+        // only the lightly-weighted last atom pins down the rotation
+        let target = [[0.0, 0.0, -0.5], [0.0, 0.0, 0.5], [0.0, 2.0, 1.0]];
+        let mobile = [[0.0, 0.0, 0.5], [0.0, 0.0, -0.5], [2.0, 0.0, -1.0]];
+        let a = Alignment::new(&mobile, &target, &[1.0, 1.0, 1e-6]).unwrap();
+        for (m, t) in mobile.iter().zip(&target) {
+            assert!(close(a.point(*m), *t), "{:?} != {t:?}", a.point(*m));
+        }
+    }
+}
+
+/// Return the Cartesian coordinates of `atom`.
+pub(crate) fn position(atom: &Atom) -> [f64; 3] {
+    [atom.x, atom.y, atom.z]
+}
+
+/// A proper rotation about the centroid of one set of points, followed by a
+/// translation onto the centroid of another.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Alignment {
+    rotation: [[f64; 3]; 3],
+    from: [f64; 3],
+    to: [f64; 3],
+}
+
+impl Alignment {
+    /// Find the rotation and translation that best superimpose `mobile` onto
+    /// the corresponding points in `target`, minimizing the sum of the squared
+    /// distances between them scaled by `weights`, by Horn's quaternion
+    /// method. Returns `None` if the lengths don't match or the weights sum to
+    /// zero.
+    pub(crate) fn new(
+        mobile: &[[f64; 3]],
+        target: &[[f64; 3]],
+        weights: &[f64],
+    ) -> Option<Self> {
+        if mobile.len() != target.len() || mobile.len() != weights.len() {
+            return None;
+        }
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let centroid = |ps: &[[f64; 3]]| -> [f64; 3] {
+            std::array::from_fn(|k| {
+                ps.iter().zip(weights).map(|(p, w)| w * p[k]).sum::<f64>()
+                    / total
+            })
+        };
+        let (from, to) = (centroid(mobile), centroid(target));
+
+        // the weighted correlation matrix of the centered points
+        let s: [[f64; 3]; 3] = std::array::from_fn(|i| {
+            std::array::from_fn(|j| {
+                mobile
+                    .iter()
+                    .zip(target)
+                    .zip(weights)
+                    .map(|((m, t), w)| w * (m[i] - from[i]) * (t[j] - to[j]))
+                    .sum()
+            })
+        });
+        let [[xx, xy, xz], [yx, yy, yz], [zx, zy, zz]] = s;
+        let n = [
+            [xx + yy + zz, yz - zy, zx - xz, xy - yx],
+            [yz - zy, xx - yy - zz, xy + yx, zx + xz],
+            [zx - xz, xy + yx, -xx + yy - zz, yz + zy],
+            [xy - yx, zx + xz, yz + zy, -xx - yy + zz],
+        ];
+        let [q0, q1, q2, q3] = largest_eigenvector(n);
+        let rotation = [
+            [
+                q0 * q0 + q1 * q1 - q2 * q2 - q3 * q3,
+                2.0 * (q1 * q2 - q0 * q3),
+                2.0 * (q1 * q3 + q0 * q2),
+            ],
+            [
+                2.0 * (q1 * q2 + q0 * q3),
+                q0 * q0 - q1 * q1 + q2 * q2 - q3 * q3,
+                2.0 * (q2 * q3 - q0 * q1),
+            ],
+            [
+                2.0 * (q1 * q3 - q0 * q2),
+                2.0 * (q2 * q3 + q0 * q1),
+                q0 * q0 - q1 * q1 - q2 * q2 + q3 * q3,
+            ],
+        ];
+        Some(Self { rotation, from, to })
+    }
+
+    /// Rotate the vector `v` without translating it.
+    pub(crate) fn vector(&self, v: [f64; 3]) -> [f64; 3] {
+        self.rotation
+            .map(|row| row.iter().zip(v).map(|(r, v)| r * v).sum())
+    }
+
+    /// Move the point `p` from the frame of the mobile points to that of the
+    /// target points.
+    pub(crate) fn point(&self, p: [f64; 3]) -> [f64; 3] {
+        let v = self.vector([
+            p[0] - self.from[0],
+            p[1] - self.from[1],
+            p[2] - self.from[2],
+        ]);
+        [v[0] + self.to[0], v[1] + self.to[1], v[2] + self.to[2]]
+    }
+
+    /// Return a copy of `atoms` moved with [Alignment::point].
+    pub(crate) fn atoms(&self, atoms: &[Atom]) -> Vec<Atom> {
+        atoms
+            .iter()
+            .map(|a| {
+                let [x, y, z] = self.point(position(a));
+                let mut a = a.clone();
+                (a.x, a.y, a.z) = (x, y, z);
+                a
+            })
+            .collect()
+    }
+
+    /// Return the angle of the rotation in degrees.
+    pub(crate) fn angle(&self) -> f64 {
+        let [[xx, _, _], [_, yy, _], [_, _, zz]] = self.rotation;
+        ((xx + yy + zz - 1.0) / 2.0)
+            .clamp(-1.0, 1.0)
+            .acos()
+            .to_degrees()
+    }
+}
+
+/// Return the normalized eigenvector of the symmetric matrix `a` with the
+/// largest eigenvalue, by cyclic Jacobi rotations.
+fn largest_eigenvector(mut a: [[f64; 4]; 4]) -> [f64; 4] {
+    let mut v = [[0.0; 4]; 4];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    for _ in 0..100 {
+        let off: f64 = (0..4)
+            .flat_map(|i| (0..4).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        let diag: f64 = (0..4).map(|i| a[i][i] * a[i][i]).sum();
+        if off <= 1e-30 * diag.max(1e-300) {
+            break;
+        }
+        for p in 0..4 {
+            for q in p + 1..4 {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum()
+                    / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in &mut a {
+                    let (rp, rq) = (row[p], row[q]);
+                    row[p] = c * rp - s * rq;
+                    row[q] = s * rp + c * rq;
+                }
+                let (ap, aq) = (a[p], a[q]);
+                a[p] = std::array::from_fn(|k| c * ap[k] - s * aq[k]);
+                a[q] = std::array::from_fn(|k| s * ap[k] + c * aq[k]);
+                for row in &mut v {
+                    let (vp, vq) = (row[p], row[q]);
+                    row[p] = c * vp - s * vq;
+                    row[q] = s * vp + c * vq;
+                }
+            }
+        }
+    }
+    let k = (0..4).max_by(|&i, &j| a[i][i].total_cmp(&a[j][j])).unwrap();
+    let norm = v.iter().map(|row| row[k] * row[k]).sum::<f64>().sqrt();
+    v.map(|row| row[k] / norm)
+}
@@ -0,0 +1,565 @@
+//! Specification of the grid of probe-atom positions
+
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_points(got: Vec<f64>, want: Vec<f64>) {
+        assert_eq!(got.len(), want.len(), "got {got:?}, want {want:?}");
+        for (g, w) in got.iter().zip(&want) {
+            assert!((g - w).abs() < 1e-12, "got {got:?}, want {want:?}");
+        }
+    }
+
+    #[test]
+    fn legacy_axis() {
+        check_points(Axis::Legacy(1..=2).points(), vec![0.1]);
+        check_points(Axis::Legacy(-3..=3).points(), vec![-0.3, -0.1, 0.1, 0.3]);
+    }
+
+    #[test]
+    fn explicit_axis() {
+        let axis: Axis = toml::from_str(
+            "start = -0.1
+             stop = 0.1
+             step = 0.05",
+        )
+        .unwrap();
+        check_points(axis.points(), vec![-0.1, -0.05, 0.0, 0.05, 0.1]);
+
+        let axis: Axis = toml::from_str(
+            "start = 1
+             stop = 2
+             step = 1
+             units = \"bohr\"",
+        )
+        .unwrap();
+        check_points(
+            axis.points(),
+            vec![BOHR_TO_ANGSTROM, 2.0 * BOHR_TO_ANGSTROM],
+        );
+
+        let axis: Axis = toml::from_str(
+            "start = 0.2
+             stop = 0.0
+             step = -0.1",
+        )
+        .unwrap();
+        check_points(axis.points(), vec![0.2, 0.1, 0.0]);
+    }
+
+    #[test]
+    fn point_label() {
+        let p = Point::Cartesian { x: 0.0, y: 0.1, z: -0.3 };
+        assert_eq!(p.label(), "x0_y0.1_z-0.3");
+        let p = Point::Polar { r: 3.0, theta: 45.0 };
+        assert_eq!(p.label(), "r3_theta45");
+        let p = Point::Cartesian { x: -0.0, y: 0.1 + 0.2, z: -0.1 - 0.2 };
+        assert_eq!(p.label(), "x0_y0.3_z-0.3");
+        // refined points closer together than the printed precision
+        let q = Point::Polar { r: 3.0, theta: 45.001 };
+        assert_ne!(p.label(), q.label());
+    }
+
+    #[test]
+    fn polar_grid() {
+        let grid: Grid = toml::from_str(
+            r#"kind = "polar"
+               r = { start = 3.0, stop = 4.0, step = 1.0 }
+               theta = { start = 0, stop = 90, step = 90 }"#,
+        )
+        .unwrap();
+        let got = grid.points();
+        assert_eq!(got.len(), 4);
+        assert_eq!(got[1], Point::Polar { r: 3.0, theta: 90.0 });
+        let [x, y, z] = got[1].cartesian();
+        check_points(vec![x, y, z], vec![0.0, 3.0, 0.0]);
+    }
+
+    #[test]
+    fn spherical_grid() {
+        let grid: Grid = toml::from_str(
+            r#"kind = "spherical"
+               r = { start = 2.0, stop = 2.0, step = 1.0 }
+               theta = { start = 90, stop = 90, step = 1 }
+               phi = { start = 0, stop = 0.5, step = 0.5, units = "radians" }"#,
+        )
+        .unwrap();
+        let got = grid.points();
+        assert_eq!(got.len(), 2);
+        let Point::Spherical { phi, .. } = got[1] else {
+            panic!("expected spherical point, got {:?}", got[1]);
+        };
+        check_points(vec![phi], vec![0.5f64.to_degrees()]);
+        let [x, y, z] = got[0].cartesian();
+        check_points(vec![x, y, z], vec![2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn mismatched_units() {
+        let grid: Grid = toml::from_str(
+            r#"kind = "polar"
+               r = { start = 3.0, stop = 4.0, step = 1.0, units = "degrees" }
+               theta = { start = 0, stop = 90, step = 90 }"#,
+        )
+        .unwrap();
+        assert!(grid.check_units().is_err());
+
+        let grid: Grid = toml::from_str(
+            r#"kind = "spherical"
+               r = { start = 2.0, stop = 2.0, step = 1.0, units = "bohr" }
+               theta = { start = 90, stop = 90, step = 1, units = "deg" }
+               phi = { start = 0, stop = 1, step = 1, units = "angstrom" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            grid.check_units(),
+            Err("phi is an angle, so it can't be in Angstrom".to_owned())
+        );
+    }
+
+    #[test]
+    fn points_grid() {
+        let grid: Grid = toml::from_str(
+            r#"kind = "points"
+               points = [[0.1, 0.3], [0.2, -0.1, 0.5]]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            grid.points(),
+            vec![
+                Point::Cartesian { x: 0.0, y: 0.1, z: 0.3 },
+                Point::Cartesian { x: 0.2, y: -0.1, z: 0.5 },
+            ]
+        );
+        assert!(Point::from_tuple(System::Polar, &[1.0, 2.0, 3.0]).is_err());
+    }
+
+    #[test]
+    fn load_csv_points() {
+        let got = load_points("testfiles/points.csv", System::Polar);
+        assert_eq!(
+            got,
+            vec![
+                Point::Polar { r: 3.0, theta: 0.0 },
+                Point::Polar { r: 3.5, theta: 45.0 },
+            ]
+        );
+    }
+}
+
+/// Conversion factor from bohr to Ångström.
+pub(crate) const BOHR_TO_ANGSTROM: f64 = 0.529_177_210_903;
+
+/// Units for an [Axis] of the grid. Lengths are converted to Ångström and
+/// angles to degrees.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Units {
+    #[serde(alias = "ang")]
+    Angstrom,
+    Bohr,
+    #[serde(alias = "deg")]
+    Degrees,
+    #[serde(alias = "rad")]
+    Radians,
+}
+
+impl Units {
+    /// Whether `self` is a unit of angle rather than of length.
+    fn is_angle(&self) -> bool {
+        matches!(self, Units::Degrees | Units::Radians)
+    }
+
+    fn scale(&self) -> f64 {
+        match self {
+            Units::Angstrom | Units::Degrees => 1.0,
+            Units::Bohr => BOHR_TO_ANGSTROM,
+            Units::Radians => 1.0f64.to_degrees(),
+        }
+    }
+}
+
+/// A single axis of the probe grid. The legacy form, `{ start, end }`, is an
+/// inclusive range of integer tenths of an Ångström, sampled at every other
+/// value. The explicit form gives a floating-point `start`, `stop`, and `step`
+/// in `units`, with `stop` included if it falls on a step. If `units` is
+/// omitted, lengths are taken to be in Ångström and angles in degrees.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum Axis {
+    Legacy(RangeInclusive<isize>),
+    Explicit {
+        start: f64,
+        stop: f64,
+        step: f64,
+        units: Option<Units>,
+    },
+}
+
+impl Axis {
+    /// Check that the units of `self`, the axis called `name`, are angles if
+    /// `angle` is set and lengths otherwise.
+    fn check_units(&self, name: &str, angle: bool) -> Result<(), String> {
+        match self {
+            Axis::Explicit { units: Some(units), .. }
+                if units.is_angle() != angle =>
+            {
+                let kind = if angle { "an angle" } else { "a length" };
+                Err(format!("{name} is {kind}, so it can't be in {units:?}"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Return the values along `self` in Ångström or degrees, the units
+    /// expected by the Z-matrix placeholders.
+    pub(crate) fn points(&self) -> Vec<f64> {
+        match self {
+            Axis::Legacy(range) => {
+                range.clone().step_by(2).map(|v| v as f64 / 10.0).collect()
+            }
+            &Axis::Explicit { start, stop, step, units } => {
+                assert!(step != 0.0, "grid step must be non-zero");
+                let scale = units.map_or(1.0, |u| u.scale());
+                // tolerate a bit of floating-point noise so that `stop` is
+                // included when it is meant to be
+                let steps = ((stop - start) / step + 1e-8).floor();
+                if steps < 0.0 {
+                    return Vec::new();
+                }
+                (0..=steps as usize)
+                    .map(|i| scale * (start + i as f64 * step))
+                    .collect()
+            }
+        }
+    }
+}
+
+/// The coordinate system of a [Point].
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum System {
+    #[default]
+    Cartesian,
+    Polar,
+    Spherical,
+}
+
+/// The kinds of grids that griddy can generate. Polar grids lie in the
+/// yz-plane with `theta` measured from the z-axis, the axis along which Molpro
+/// orients a linear molecule. Spherical grids additionally rotate by `phi`
+/// about the z-axis, starting from the xz-plane. A `points` grid is an explicit
+/// list of coordinate tuples in `system`, as described in [Point::from_tuple].
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub(crate) enum Grid {
+    Cartesian {
+        x: Option<Axis>,
+        y: Axis,
+        z: Axis,
+    },
+    Polar {
+        r: Axis,
+        theta: Axis,
+    },
+    Spherical {
+        r: Axis,
+        theta: Axis,
+        phi: Axis,
+    },
+    Points {
+        #[serde(default)]
+        system: System,
+        points: Vec<Vec<f64>>,
+    },
+}
+
+impl Grid {
+    /// Return the coordinate system of the points in `self`.
+    pub(crate) fn system(&self) -> System {
+        match self {
+            Grid::Cartesian { .. } => System::Cartesian,
+            Grid::Polar { .. } => System::Polar,
+            Grid::Spherical { .. } => System::Spherical,
+            Grid::Points { system, .. } => *system,
+        }
+    }
+
+    /// Check that each axis of `self` is given in units of the right kind:
+    /// lengths for x, y, z, and r, and angles for theta and phi.
+    pub(crate) fn check_units(&self) -> Result<(), String> {
+        match self {
+            Grid::Cartesian { x, y, z } => {
+                if let Some(x) = x {
+                    x.check_units("x", false)?;
+                }
+                y.check_units("y", false)?;
+                z.check_units("z", false)
+            }
+            Grid::Polar { r, theta } => {
+                r.check_units("r", false)?;
+                theta.check_units("theta", true)
+            }
+            Grid::Spherical { r, theta, phi } => {
+                r.check_units("r", false)?;
+                theta.check_units("theta", true)?;
+                phi.check_units("phi", true)
+            }
+            Grid::Points { .. } => Ok(()),
+        }
+    }
+
+    /// Generate all of the points in the grid. Cartesian grids step fastest
+    /// along x and slowest along z, while polar and spherical grids step
+    /// fastest along their angular coordinates. Panics if any axis has the
+    /// wrong kind of units.
+    pub(crate) fn points(&self) -> Vec<Point> {
+        if let Err(e) = self.check_units() {
+            panic!("invalid grid: {e}");
+        }
+        let mut ret = Vec::new();
+        match self {
+            Grid::Cartesian { x, y, z } => {
+                let xs = x.as_ref().map_or_else(|| vec![0.0], Axis::points);
+                let ys = y.points();
+                for z in z.points() {
+                    for &y in &ys {
+                        for &x in &xs {
+                            ret.push(Point::Cartesian { x, y, z });
+                        }
+                    }
+                }
+            }
+            Grid::Polar { r, theta } => {
+                let thetas = theta.points();
+                for r in r.points() {
+                    for &theta in &thetas {
+                        ret.push(Point::Polar { r, theta });
+                    }
+                }
+            }
+            Grid::Spherical { r, theta, phi } => {
+                let thetas = theta.points();
+                let phis = phi.points();
+                for r in r.points() {
+                    for &theta in &thetas {
+                        for &phi in &phis {
+                            ret.push(Point::Spherical { r, theta, phi });
+                        }
+                    }
+                }
+            }
+            Grid::Points { system, points } => {
+                for p in points {
+                    ret.push(Point::from_tuple(*system, p).unwrap());
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Load an explicit list of points in `system` from the file at `path`. JSON
+/// files, identified by a `.json` extension, should contain an array of arrays.
+/// Any other file is read as CSV with one point per line. Blank lines and
+/// lines starting with `#` are ignored.
+pub(crate) fn load_points(
+    path: impl AsRef<Path>,
+    system: System,
+) -> Vec<Point> {
+    let path = path.as_ref();
+    let s = read_to_string(path).unwrap_or_else(|e| {
+        panic!("failed to read points from {}: {e}", path.display())
+    });
+    let tuples: Vec<Vec<f64>> =
+        if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&s).unwrap()
+        } else {
+            parse_csv(&s)
+        };
+    tuples
+        .iter()
+        .map(|t| Point::from_tuple(system, t).unwrap())
+        .collect()
+}
+
+fn parse_csv(s: &str) -> Vec<Vec<f64>> {
+    s.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.split(',')
+                .map(|f| {
+                    f.trim().parse().unwrap_or_else(|e| {
+                        panic!("failed to parse `{f}` in `{line}`: {e}")
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// A single position of the probe atom. Lengths are in Ångström and angles in
+/// degrees.
+///
+/// The variants are ordered from most to fewest fields so that the untagged
+/// deserialization picks the right one, and `x` defaults to 0 for
+/// compatibility with checkpoints from 2-D grids.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Point {
+    Spherical {
+        r: f64,
+        theta: f64,
+        phi: f64,
+    },
+    Polar {
+        r: f64,
+        theta: f64,
+    },
+    Cartesian {
+        #[serde(default)]
+        x: f64,
+        y: f64,
+        z: f64,
+    },
+}
+
+impl Point {
+    /// Build a [Point] from a tuple of coordinates in `system`. Cartesian
+    /// tuples can be either `(y, z)` or `(x, y, z)`, polar tuples are
+    /// `(r, theta)`, and spherical tuples are `(r, theta, phi)`.
+    pub(crate) fn from_tuple(
+        system: System,
+        tuple: &[f64],
+    ) -> Result<Self, String> {
+        Ok(match (system, tuple) {
+            (System::Cartesian, &[y, z]) => Point::Cartesian { x: 0.0, y, z },
+            (System::Cartesian, &[x, y, z]) => Point::Cartesian { x, y, z },
+            (System::Polar, &[r, theta]) => Point::Polar { r, theta },
+            (System::Spherical, &[r, theta, phi]) => {
+                Point::Spherical { r, theta, phi }
+            }
+            _ => {
+                return Err(format!(
+                    "invalid number of coordinates for {system:?} point: \
+                     {tuple:?}"
+                ))
+            }
+        })
+    }
+
+    /// Return the coordinate system of `self`.
+    pub(crate) fn system(&self) -> System {
+        match self {
+            Point::Cartesian { .. } => System::Cartesian,
+            Point::Polar { .. } => System::Polar,
+            Point::Spherical { .. } => System::Spherical,
+        }
+    }
+
+    /// Return the names of the coordinates of `self`, which are also the
+    /// names of the corresponding Z-matrix placeholders, paired with their
+    /// values.
+    pub(crate) fn coords(&self) -> Vec<(&'static str, f64)> {
+        match *self {
+            Point::Cartesian { x, y, z } => vec![("x", x), ("y", y), ("z", z)],
+            Point::Polar { r, theta } => vec![("r", r), ("theta", theta)],
+            Point::Spherical { r, theta, phi } => {
+                vec![("r", r), ("theta", theta), ("phi", phi)]
+            }
+        }
+    }
+
+    /// Convert `self` to Cartesian coordinates in Ångström.
+    pub(crate) fn cartesian(&self) -> [f64; 3] {
+        match *self {
+            Point::Cartesian { x, y, z } => [x, y, z],
+            Point::Polar { r, theta } => {
+                let theta = theta.to_radians();
+                [0.0, r * theta.sin(), r * theta.cos()]
+            }
+            Point::Spherical { r, theta, phi } => {
+                let (theta, phi) = (theta.to_radians(), phi.to_radians());
+                [
+                    r * theta.sin() * phi.cos(),
+                    r * theta.sin() * phi.sin(),
+                    r * theta.cos(),
+                ]
+            }
+        }
+    }
+
+    /// Replace the `{{name}}` placeholders in `template` with the coordinates
+    /// of `self`.
+    pub(crate) fn fill(&self, template: &str) -> String {
+        let mut ret = template.to_owned();
+        for (name, v) in self.coords() {
+            ret = ret.replace(&format!("{{{{{name}}}}}"), &v.to_string());
+        }
+        ret
+    }
+
+    /// Return a table header matching the [Display] implementation for
+    /// `self`.
+    pub(crate) fn header(&self) -> String {
+        let mut ret = Vec::new();
+        for (name, _) in self.coords() {
+            ret.push(format!("{name:>w$}", w = width(name)));
+        }
+        ret.join(" ")
+    }
+
+    /// Return a name for `self` suitable for use as a file or directory name,
+    /// like `x0_y0.1_z-0.3`. The coordinates are rounded to six decimal
+    /// places, hiding any floating-point noise from stepping along the axes,
+    /// but still far finer than any useful grid spacing, so that distinct
+    /// points never share a name.
+    pub(crate) fn label(&self) -> String {
+        let coords: Vec<_> = self
+            .coords()
+            .into_iter()
+            .map(|(name, v)| format!("{name}{}", label_value(v)))
+            .collect();
+        coords.join("_")
+    }
+}
+
+/// Format `v` for [Point::label], with six decimal places and any trailing
+/// zeros removed.
+fn label_value(v: f64) -> String {
+    let s = format!("{v:.6}");
+    match s.trim_end_matches('0').trim_end_matches('.') {
+        "-0" => "0".to_owned(),
+        s => s.to_owned(),
+    }
+}
+
+/// The column width of the coordinate called `name`. Angles need extra room
+/// for three digits before the decimal point.
+fn width(name: &str) -> usize {
+    match name {
+        "theta" | "phi" => 7,
+        _ => 5,
+    }
+}
+
+impl Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (name, v)) in self.coords().into_iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{v:w$.2}", w = width(name))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+//! Host–probe interaction energies, with optional counterpoise correction
+
+use std::fmt::Display;
+
+use log::warn;
+use psqs::geom::Geom;
+use psqs::program::Template;
+use psqs::queue::Check;
+use serde::Deserialize;
+use symm::Atom;
+
+use crate::grid::Point;
+use crate::model::HARTREE_TO_CM;
+use crate::runner::{Calc, Runner};
+use crate::{Config, OptOutput};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{InProcess, Potential};
+
+    #[test]
+    fn model_interaction() {
+        let config = Config::load("testfiles/model.toml");
+        let model = config.model.clone().unwrap();
+        let geom = vec![
+            Atom::new(1, 0.0, 0.0, -0.9),
+            Atom::new(8, 0.0, 0.0, 0.07),
+            Atom::new(2, 0.0, 2.0, -2.6),
+        ];
+        let opt = OptOutput {
+            point: Point::Cartesian { x: 0.0, y: 2.0, z: -2.6 },
+            ref_energy: Some(model.energy(&geom)),
+            geom: Some(geom.clone()),
+            nominal: None,
+            probe: None,
+        };
+        let runner = InProcess::new(model.clone(), 1);
+        let settings = Interaction {
+            counterpoise: true,
+            template: Some("ghost {{ghosts}}".to_owned()),
+        };
+        let got =
+            interaction_energies(&config, &settings, &runner, "int", &[opt]);
+        assert_eq!(got.len(), 1);
+
+        let want =
+            (model.energy(&geom) - model.energy(&geom[..2])) * HARTREE_TO_CM;
+        let (_, InteractionEnergy { raw, cp }) = got[0];
+        assert!(want < -1.0);
+        assert!((raw - want).abs() < 1e-8, "{raw} != {want}");
+        // the model potential has no basis set superposition error
+        assert!((cp.unwrap() - raw).abs() < 1e-8);
+    }
+}
+
+/// Settings for computing the interaction energy between the host and the
+/// probe, the last atom, at each optimized grid point.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct Interaction {
+    /// Also compute the counterpoise-corrected interaction energy from the
+    /// energies of each monomer in the basis of the whole complex. This
+    /// requires `template`.
+    #[serde(default)]
+    pub(crate) counterpoise: bool,
+
+    /// The template for the counterpoise calculations, with a `{{ghosts}}`
+    /// placeholder where the program expects the list of ghost atoms. The
+    /// other calculations use the main template, which should not contain
+    /// the placeholder.
+    pub(crate) template: Option<String>,
+}
+
+/// The interaction energy in cm⁻¹ at a single grid point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct InteractionEnergy {
+    pub(crate) raw: f64,
+    /// The counterpoise-corrected interaction energy, if requested.
+    pub(crate) cp: Option<f64>,
+}
+
+impl InteractionEnergy {
+    /// Return a table header matching the [Display] implementation.
+    pub(crate) fn header() -> String {
+        format!(" {:>10} {:>10}", "eint", "eint_cp")
+    }
+}
+
+impl Display for InteractionEnergy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, " {:10.2}", self.raw)?;
+        match self.cp {
+            Some(cp) => write!(f, " {cp:10.2}"),
+            None => write!(f, " {:>10}", "-"),
+        }
+    }
+}
+
+/// Build the monomer calculations for `geom`, starting at `index`: the host
+/// and probe on their own, followed by each of them with the other as ghost
+/// atoms, using the template `counterpoise`, if it is provided. The host
+/// carries the whole charge of the complex, leaving the probe neutral.
+fn monomers(
+    config: &Config,
+    dir: &str,
+    geom: &[Atom],
+    index: usize,
+    counterpoise: Option<&Template>,
+) -> Vec<Calc> {
+    let template = Template::from(&config.pbqff.template);
+    let charge = config.pbqff.charge;
+    let nhost = geom.len() - 1;
+    let mut jobs = vec![
+        (geom[..nhost].to_vec(), Vec::new(), charge, &template),
+        (geom[nhost..].to_vec(), Vec::new(), 0, &template),
+    ];
+    if let Some(cp) = counterpoise {
+        jobs.push((geom.to_vec(), vec![nhost], charge, cp));
+        jobs.push((geom.to_vec(), (0..nhost).collect(), 0, cp));
+    }
+    jobs.into_iter()
+        .enumerate()
+        .map(|(i, (atoms, ghosts, charge, template))| Calc {
+            filename: format!("{dir}/int.{:08}", index + i),
+            template: template.clone(),
+            charge,
+            geom: Geom::Xyz(atoms),
+            index: index + i,
+            ghosts,
+        })
+        .collect()
+}
+
+/// Compute the interaction energy for each of `opts`, skipping any points
+/// where one of the monomer calculations fails.
+pub(crate) fn interaction_energies(
+    config: &Config,
+    settings: &Interaction,
+    runner: &impl Runner,
+    dir: &str,
+    opts: &[OptOutput],
+) -> Vec<(Point, InteractionEnergy)> {
+    let per_point = if settings.counterpoise { 4 } else { 2 };
+    let counterpoise = settings.counterpoise.then(|| {
+        let template = settings
+            .template
+            .as_ref()
+            .expect("counterpoise requires an interaction template");
+        if !template.contains("{{ghosts}}") {
+            warn!("counterpoise template has no {{{{ghosts}}}} placeholder");
+        }
+        Template::from(template)
+    });
+    let mut calcs = Vec::new();
+    let mut points = Vec::new();
+    for opt in opts {
+        let (Some(e), Some(geom)) = (opt.ref_energy, &opt.geom) else {
+            continue;
+        };
+        let jobs =
+            monomers(config, dir, geom, calcs.len(), counterpoise.as_ref());
+        calcs.extend(jobs);
+        points.push((opt.point, e));
+    }
+
+    let mut energies = vec![0.0; calcs.len()];
+    let failed =
+        match runner.single_points(dir, calcs, &mut energies, Check::None) {
+            Ok(()) => Vec::new(),
+            Err(e) => e,
+        };
+
+    let mut ret = Vec::new();
+    let chunks = energies.chunks(per_point).enumerate();
+    for ((point, total), (i, e)) in points.into_iter().zip(chunks) {
+        let start = i * per_point;
+        if failed
+            .iter()
+            .any(|f| (start..start + per_point).contains(f))
+        {
+            warn!("skipping interaction energy at ({point}) with failed jobs");
+            continue;
+        }
+        let raw = (total - e[0] - e[1]) * HARTREE_TO_CM;
+        let cp = settings
+            .counterpoise
+            .then(|| (total - e[2] - e[3]) * HARTREE_TO_CM);
+        ret.push((point, InteractionEnergy { raw, cp }));
+    }
+    ret
+}
@@ -1,86 +1,654 @@
-use std::fs::read_to_string;
-use std::io::stderr;
-use std::ops::{Range, RangeInclusive};
+use std::fs::{read_to_string, File};
+use std::ops::Range;
 use std::path::Path;
 
+use adaptive::Adaptive;
+use clap::builder::RangedU64ValueParser;
 use clap::Parser;
-use log::{info, warn};
+use grid::{Axis, Grid, Point, System};
+use interaction::{Interaction, InteractionEnergy};
+use log::{debug, info, warn};
+use model::Model;
+use output::{Record, Status};
 use pbqff::cleanup;
 use pbqff::coord_type::cart::freqs;
 use pbqff::coord_type::findiff::bighash::{BigHash, Target};
 use pbqff::coord_type::findiff::FiniteDifference;
 use pbqff::coord_type::{Cart, Derivative, FirstPart};
+use probe::ProbeGeometry;
 use psqs::geom::Geom;
 use psqs::max_threads;
+use psqs::program::cfour::Cfour;
+use psqs::program::dftbplus::DFTBPlus;
 use psqs::program::molpro::Molpro;
-use psqs::program::{Job, Program, Template};
+use psqs::program::mopac::Mopac;
+use psqs::program::{Program, ProgramResult, Template};
+use psqs::queue::local::Local;
 use psqs::queue::pbs::Pbs;
+use psqs::queue::slurm::Slurm;
 use psqs::queue::{Check, Queue};
-use serde::Deserialize;
+use retry::Retry;
+use runner::{Calc, Cluster, InProcess, Runner, Unfrozen};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use symm::{Atom, Molecule};
+use symmetry::Symmetry;
+
+mod adaptive;
+mod align;
+mod grid;
+mod interaction;
+mod model;
+mod modes;
+mod output;
+mod probe;
+mod retry;
+mod runner;
+mod seed;
+mod symmetry;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use model::tests::MU_OH;
+    use model::wavenumber;
+    use runner::Potential;
+
+    /// Return the path to a fresh, empty directory called `name` in the system
+    /// temporary directory, for tests that write files.
+    pub(crate) fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.to_str().unwrap().to_owned()
+    }
 
     #[test]
     fn load_config() {
         let got = Config::load("testfiles/pbqff.toml");
-        assert_eq!(got.yrange, 1..=2);
-        assert_eq!(got.zrange, -3..=3);
+        assert_eq!(
+            got.grid(),
+            Grid::Cartesian {
+                x: None,
+                y: Axis::Legacy(1..=2),
+                z: Axis::Legacy(-3..=3),
+            }
+        );
+    }
+
+    #[test]
+    fn three_d_grid() {
+        let axis = Axis::Legacy(-1..=1);
+        let grid =
+            Grid::Cartesian { x: Some(axis.clone()), y: axis.clone(), z: axis };
+        let got = build_opt_inputs("He {{x}} {{y}} {{z}}", grid.points());
+        assert_eq!(got.len(), 8);
+        let OptInput { point, geometry } = &got[1];
+        assert_eq!(*point, Point::Cartesian { x: 0.1, y: -0.1, z: -0.1 });
+        let Geom::Zmat(s) = geometry else {
+            panic!("expected Z-matrix geometry");
+        };
+        assert_eq!(s, "He 0.1 -0.1 -0.1");
+    }
+
+    /// A harmonic bond between the first two atoms, ignoring any others.
+    struct Harmonic {
+        k: f64,
+        r0: f64,
+    }
+
+    impl Potential for Harmonic {
+        fn energy(&self, atoms: &[Atom]) -> f64 {
+            let (a, b) = (&atoms[0], &atoms[1]);
+            let r = ((a.x - b.x).powi(2)
+                + (a.y - b.y).powi(2)
+                + (a.z - b.z).powi(2))
+            .sqrt();
+            0.5 * self.k * (r - self.r0).powi(2)
+        }
+    }
+
+    #[test]
+    fn in_process() {
+        let config = Config::load("testfiles/local.toml");
+        let runner = InProcess::new(Harmonic { k: 1.8, r0: 0.97 }, 1);
+        let points = config.grid().points();
+        let opt_dir = test_dir("griddy_in_process_opt");
+        let pts_dir = test_dir("griddy_in_process_pts");
+        let opts =
+            optimize_points(&config, &runner, &opt_dir, points.clone(), false);
+        assert_eq!(opts.len(), points.len());
+        for opt in &opts {
+            let geom = opt.geom.as_ref().unwrap();
+            let [h, o, _] = geom.as_slice() else {
+                panic!("expected three atoms, got {geom:?}");
+            };
+            assert!(((h.z - o.z).abs() - 0.97).abs() < 1e-6);
+            let probe = opt.probe.unwrap();
+            let want = opt.point.cartesian();
+            assert!(probe.iter().zip(want).all(|(p, w)| (p - w).abs() < 1e-8));
+        }
+
+        let got = frequencies(&config, &runner, &pts_dir, opts, false, None);
+        std::fs::remove_dir_all(opt_dir).unwrap();
+        std::fs::remove_dir_all(pts_dir).unwrap();
+        assert_eq!(got.len(), points.len());
+        let want = wavenumber(1.8, MU_OH);
+        for (point, freqs) in got {
+            assert_eq!(freqs.harms.len(), 1);
+            assert!(
+                (freqs.harms[0] - want).abs() < 0.1,
+                "{point}: got {}, want {want}",
+                freqs.harms[0]
+            );
+        }
+    }
+
+    #[test]
+    fn mode_table() {
+        let freqs = Freqs {
+            harms: vec![3700.0, 150.5],
+            corrs: vec![3500.25],
+            modes: Vec::new(),
+            geom: Vec::new(),
+            rot_consts: Vec::new(),
+        };
+        assert_eq!(mode_header(&[2], ""), "    harm2    corr2");
+        assert_eq!(mode_header(&[1], "d"), "   dharm1   dcorr1");
+        assert_eq!(
+            mode_columns(&freqs, &[1, 2, 3]),
+            "  3700.00  3500.25   150.50        -        -        -"
+        );
+    }
+
+    /// A [Runner] that records the starting geometries of each batch of
+    /// optimizations before passing them on to `inner`.
+    struct Recording<R> {
+        inner: R,
+        batches: std::sync::Mutex<Vec<Vec<Geom>>>,
+    }
+
+    impl<R: Runner> Runner for Recording<R> {
+        fn optimize(
+            &self,
+            dir: &str,
+            calcs: Vec<Calc>,
+            dst: &mut [ProgramResult],
+            reuse: bool,
+        ) -> Result<f64, Vec<usize>> {
+            let geoms = calcs.iter().map(|c| c.geom.clone()).collect();
+            self.batches.lock().unwrap().push(geoms);
+            self.inner.optimize(dir, calcs, dst, reuse)
+        }
+
+        fn single_points(
+            &self,
+            dir: &str,
+            calcs: Vec<Calc>,
+            dst: &mut [f64],
+            check: Check,
+        ) -> Result<(), Vec<usize>> {
+            self.inner.single_points(dir, calcs, dst, check)
+        }
+    }
+
+    #[test]
+    fn seeded_sweep() {
+        let mut config = Config::load("testfiles/local.toml");
+        let runner = Recording {
+            inner: InProcess::new(Harmonic { k: 1.8, r0: 0.97 }, 1),
+            batches: Default::default(),
+        };
+        let points = config.grid().points();
+        let dir = test_dir("griddy_seeded_sweep");
+        let want =
+            optimize_points(&config, &runner, &dir, points.clone(), false);
+        runner.batches.lock().unwrap().clear();
+        config.seed = true;
+        let got = optimize_points(&config, &runner, &dir, points, false);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        // the two points at z = 0 run together from the template, with its
+        // 0.96 Å bond, and the point at z = 4 starts from their 0.97 Å bond
+        let bond = |geom: &Geom| {
+            let atoms = runner::cartesian(geom).unwrap();
+            (atoms[0].z - atoms[1].z).abs()
+        };
+        let batches = runner.batches.lock().unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].len(), 2);
+        for geom in &batches[0] {
+            assert!((bond(geom) - 0.96).abs() < 1e-8);
+        }
+        assert!((bond(&batches[1][0]) - 0.97).abs() < 1e-6);
+
+        assert_eq!(got.len(), want.len());
+        for (g, w) in got.iter().zip(&want) {
+            assert_eq!(g.point, w.point);
+            let (g, w) = (g.geom.as_ref().unwrap(), w.geom.as_ref().unwrap());
+            for (a, b) in g.iter().zip(w) {
+                assert!((a.x - b.x).abs() < 1e-6);
+                assert!((a.y - b.y).abs() < 1e-6);
+                assert!((a.z - b.z).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn freeze_probe() {
+        let config = Config::load("testfiles/model.toml");
+        assert!(config.freeze_probe);
+        let points = config.grid().points();
+        let runner = config.model_runner().unwrap();
+        let dir = test_dir("griddy_freeze_probe");
+        let got =
+            optimize_points(&config, &runner, &dir, points.clone(), false);
+        assert_eq!(got.len(), points.len());
+
+        // let the probe move, so that only the distant point survives
+        let runner = InProcess::new(config.model.clone().unwrap(), 0);
+        let got =
+            optimize_points(&config, &runner, &dir, points.clone(), false);
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].point, points[0]);
+    }
+
+    /// A [Runner] that turns the optimized geometries from `inner` upside
+    /// down, by a C₂ rotation about the x-axis, like a program reorienting
+    /// them.
+    struct Upturned<R>(R);
+
+    impl<R: Runner> Runner for Upturned<R> {
+        fn optimize(
+            &self,
+            dir: &str,
+            calcs: Vec<Calc>,
+            dst: &mut [ProgramResult],
+            reuse: bool,
+        ) -> Result<f64, Vec<usize>> {
+            let ret = self.0.optimize(dir, calcs, dst, reuse);
+            for geom in dst.iter_mut().flat_map(|r| r.cart_geom.iter_mut()) {
+                for a in geom {
+                    (a.y, a.z) = (-a.y, -a.z);
+                }
+            }
+            ret
+        }
+
+        fn single_points(
+            &self,
+            dir: &str,
+            calcs: Vec<Calc>,
+            dst: &mut [f64],
+            check: Check,
+        ) -> Result<(), Vec<usize>> {
+            self.0.single_points(dir, calcs, dst, check)
+        }
+    }
+
+    #[test]
+    fn undo_rotation() {
+        let mut config = Config::load("testfiles/local.toml");
+        config.freeze_probe = true;
+        let runner = Upturned(InProcess::new(Harmonic { k: 1.8, r0: 0.97 }, 1));
+        let points = config.grid().points();
+        let dir = test_dir("griddy_undo_rotation");
+
+        // the probe is still where it was requested in the frame of the grid,
+        // but the geometry is left upside down
+        let got =
+            optimize_points(&config, &runner, &dir, points.clone(), false);
+        assert_eq!(got.len(), points.len());
+        for opt in &got {
+            let h = &opt.geom.as_ref().unwrap()[0];
+            assert!((h.z - 0.905).abs() < 1e-6, "{h:?}");
+            let probe = opt.probe.unwrap();
+            let want = opt.point.cartesian();
+            assert!(probe.iter().zip(want).all(|(p, w)| (p - w).abs() < 1e-6));
+        }
+
+        config.canonicalize = true;
+        let got =
+            optimize_points(&config, &runner, &dir, points.clone(), false);
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(got.len(), points.len());
+        for opt in &got {
+            let geom = opt.geom.as_ref().unwrap();
+            assert!((geom[0].z + 0.905).abs() < 1e-6, "{geom:?}");
+            let p = align::position(&geom[2]);
+            let want = opt.point.cartesian();
+            assert!(p.iter().zip(want).all(|(p, w)| (p - w).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn seed_from_neighbour() {
+        let template = "H 0.0 0.0 -0.9\nO 0.0 0.0 0.06\nHe {{x}} {{y}} {{z}}";
+        let points: Vec<_> = [0.0, 0.5, 2.0]
+            .into_iter()
+            .map(|z| Point::Cartesian { x: 0.0, y: 0.0, z })
+            .collect();
+        // stretched and reoriented along the x-axis by the program
+        let res: Vec<_> = points
+            .iter()
+            .map(|p| ProgramResult {
+                cart_geom: Some(vec![
+                    Atom::new(1, 0.5 - p.cartesian()[2], 0.0, 0.0),
+                    Atom::new(8, -0.47 - p.cartesian()[2], 0.0, 0.0),
+                    Atom::new(2, 0.0, 1.0, 0.0),
+                ]),
+                ..Default::default()
+            })
+            .collect();
+        // the nearest point is 1, but it failed too
+        let Some(geom) = neighbour_seed(template, &points, 2, &res, &[1, 2])
+        else {
+            panic!("expected a seed geometry");
+        };
+        let got = runner::cartesian(&geom).unwrap();
+        // the host is stretched, but stays in the frame of the template, and
+        // the probe is placed from the grid
+        assert!((got[0].z + 0.905).abs() < 1e-8);
+        assert!((got[1].z - 0.065).abs() < 1e-8);
+        assert_eq!(got[2], Atom::new(2, 0.0, 0.0, 2.0));
+        assert!(
+            neighbour_seed(template, &points, 0, &res, &[0, 1, 2]).is_none()
+        );
+    }
+
+    #[test]
+    fn resume_layout() {
+        let config = Config::load("testfiles/local.toml");
+        let runner = InProcess::new(Harmonic { k: 1.8, r0: 0.97 }, 1);
+        let points = config.grid().points();
+        let opt_dir = test_dir("griddy_resume_layout_opt");
+        let dir = test_dir("griddy_resume_layout");
+        let layout = Path::new(&dir).join(LAYOUT_FILE);
+
+        let opts =
+            optimize_points(&config, &runner, &opt_dir, points.clone(), false);
+        let want = frequencies(&config, &runner, &dir, opts, false, None);
+        let full: Vec<(Point, Range<usize>)> = load_layout(&layout).unwrap();
+        assert_eq!(full.len(), points.len());
+
+        // resuming the same jobs keeps the layout
+        let opts =
+            optimize_points(&config, &runner, &opt_dir, points.clone(), false);
+        let got = frequencies(&config, &runner, &dir, opts, true, None);
+        assert_eq!(load_layout(&layout), Some(full.clone()));
+        assert_eq!(got.len(), want.len());
+        for ((gp, gf), (wp, wf)) in got.iter().zip(&want) {
+            assert_eq!(gp, wp);
+            assert_eq!(gf.harms, wf.harms);
+        }
+
+        // but a different set of jobs starts over
+        let opts = optimize_points(
+            &config,
+            &runner,
+            &opt_dir,
+            points[1..].to_vec(),
+            false,
+        );
+        frequencies(&config, &runner, &dir, opts, true, None);
+        let got: Vec<(Point, Range<usize>)> = load_layout(&layout).unwrap();
+        std::fs::remove_dir_all(opt_dir).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(got.len(), points.len() - 1);
+        assert_ne!(got[0], full[0]);
     }
 }
 
+/// Run the optimizations in `geoms` in `opt_dir`. If `resume` is set and the
+/// grid points match those of an interrupted run, any complete output files
+/// from that run are reused.
 fn optimize(
     opt_dir: impl AsRef<Path>,
-    queue: &Pbs,
+    runner: &impl Runner,
     geoms: Vec<OptInput>,
-    template: Template,
-    charge: isize,
+    config: &Config,
+    resume: bool,
 ) -> Vec<OptOutput> {
     let opt_dir = opt_dir.as_ref();
-    let mut jobs = Vec::new();
+    let template = Template::from(&config.pbqff.template);
+    let mut calcs = Vec::new();
     let mut ret = Vec::new();
     for (i, geom) in geoms.into_iter().enumerate() {
         let opt_file = opt_dir.join("opt").to_str().unwrap().to_owned();
-        jobs.push(Job::new(
-            Molpro::new(
-                opt_file + &i.to_string(),
-                template.clone(),
-                charge,
-                geom.geometry,
-            ),
-            i,
-        ));
         ret.push(OptOutput {
-            y: geom.y,
-            z: geom.z,
+            point: geom.point,
             ref_energy: None,
             geom: None,
+            nominal: Some(
+                probe_position(&geom.geometry)
+                    .unwrap_or(geom.point.cartesian()),
+            ),
+            probe: None,
+        });
+        calcs.push(Calc {
+            filename: opt_file + &i.to_string(),
+            template: template.clone(),
+            charge: config.pbqff.charge,
+            geom: geom.geometry,
+            index: i,
+            ghosts: Vec::new(),
         });
     }
-    let mut res = vec![Default::default(); jobs.len()];
-    let res = match queue.energize(opt_dir.to_str().unwrap(), jobs, &mut res) {
+    let mut hosts: Vec<_> = calcs
+        .iter()
+        .map(|c| reference_host(config, &c.geom))
+        .collect();
+    let dir = opt_dir.to_str().unwrap();
+    let points: Vec<_> = ret.iter().map(|o| o.point).collect();
+    let reuse = same_jobs(opt_dir, &points, resume);
+    let template = geom_template(config);
+    let mut res = vec![Default::default(); calcs.len()];
+    let status = if config.seed {
+        sweep(runner, dir, template, &calcs, &points, &mut res, reuse)
+    } else {
+        runner.optimize(dir, calcs.clone(), &mut res, reuse)
+    };
+    let res = match status {
         Ok(time) => {
             info!("total optimize time: {time:.2} s");
             res
         }
         Err(failed_indices) => {
+            let failed_indices = config.opt_retry.optimize(
+                runner,
+                dir,
+                &calcs,
+                failed_indices,
+                &mut res,
+                |i, res, failed| {
+                    neighbour_seed(template, &points, i, res, failed)
+                },
+            );
             info!("filtering out {} failed indices", failed_indices.len());
             assert_eq!(res.len(), ret.len());
             let res = filter_failed(res, &failed_indices);
             ret = filter_failed(ret, &failed_indices);
+            hosts = filter_failed(hosts, &failed_indices);
             assert_eq!(res.len(), ret.len());
             res
         }
     };
 
+    let mut moved = Vec::new();
+    let mut warned = false;
     for (i, r) in res.into_iter().enumerate() {
+        let mut geom = r.cart_geom.unwrap();
+        let point = ret[i].point;
+        let alignment = hosts[i]
+            .as_ref()
+            .zip(ret[i].nominal)
+            .and_then(|(h, n)| probe::host_alignment(&geom, h, n));
+        // the probe in the frame of the grid
+        let aligned = alignment
+            .as_ref()
+            .zip(geom.last())
+            .map(|(a, p)| a.point(align::position(p)));
+        match &alignment {
+            Some(a) if a.angle() > ROTATION_TOL => {
+                let angle = a.angle();
+                if config.canonicalize {
+                    info!(
+                        "undoing {angle:.1}° rotation at grid point ({point})"
+                    );
+                    geom = a.atoms(&geom);
+                } else {
+                    warn!(
+                        "host is rotated by {angle:.1}° at grid point \
+                         ({point})"
+                    );
+                }
+            }
+            Some(_) => {}
+            None if !warned && (config.canonicalize || config.freeze_probe) => {
+                warn!(
+                    "no reference host geometry to align the optimized \
+                     geometries onto, starting at grid point ({point})"
+                );
+                warned = true;
+            }
+            None => {}
+        }
+        let probe = aligned.or(geom.last().map(align::position));
+        if let (true, Some(p), Some(n)) =
+            (config.freeze_probe, aligned, ret[i].nominal)
+        {
+            let d = p.iter().zip(n).map(|(p, n)| (p - n).powi(2));
+            let d = d.sum::<f64>().sqrt();
+            if d > PROBE_TOL {
+                warn!("probe moved by {d:.2e} Å at grid point ({point})");
+                moved.push(i);
+            }
+        }
         ret[i].ref_energy = Some(r.energy);
-        ret[i].geom = Some(r.cart_geom.unwrap());
+        ret[i].geom = Some(geom);
+        ret[i].probe = probe;
     }
 
-    ret
+    if !moved.is_empty() {
+        info!("filtering out {} points where the probe moved", moved.len());
+    }
+    filter_failed(ret, &moved)
+}
+
+/// The largest distance in Å that the probe can move during an optimization
+/// with [Config::freeze_probe] set.
+const PROBE_TOL: f64 = 1e-4;
+
+/// The largest rotation in degrees of an optimized host relative to its
+/// reference geometry that is put down to the optimization itself rather than
+/// to the program reorienting the molecule.
+const ROTATION_TOL: f64 = 1.0;
+
+/// Return the host geometry in the intended orientation for an optimization
+/// starting from `geom`: `geom` without the probe, the last atom, if it is in
+/// Cartesian coordinates, or else `config.host`.
+fn reference_host(config: &Config, geom: &Geom) -> Option<Vec<Atom>> {
+    match runner::cartesian(geom) {
+        Some(mut atoms) => {
+            atoms.pop();
+            Some(atoms)
+        }
+        None => config.host().map(|m| m.atoms),
+    }
+}
+
+/// Return the position of the probe, the last atom, in `geom`, if `geom` is
+/// given in Cartesian coordinates.
+fn probe_position(geom: &Geom) -> Option<[f64; 3]> {
+    let atoms = runner::cartesian(geom)?;
+    let probe = atoms.last()?;
+    Some([probe.x, probe.y, probe.z])
+}
+
+/// Run the optimizations in `calcs` in waves of grid points that share their
+/// slowest-varying coordinate, starting each from the host geometry of the
+/// nearest point in an earlier wave, as described in [neighbour_seed]. The
+/// points in each wave are run in parallel. `points` gives the grid point of
+/// each calculation, and `reuse` is passed along to [Runner::optimize].
+fn sweep(
+    runner: &impl Runner,
+    dir: &str,
+    template: &str,
+    calcs: &[Calc],
+    points: &[Point],
+    res: &mut [ProgramResult],
+    reuse: bool,
+) -> Result<f64, Vec<usize>> {
+    let mut order: Vec<_> = calcs.iter().collect();
+    order.sort_by(|a, b| adaptive::compare(&points[a.index], &points[b.index]));
+    let wave = |c: &Calc| adaptive::grid_order(&points[c.index])[0];
+    let mut waves: Vec<Vec<&Calc>> = Vec::new();
+    for calc in order {
+        match waves.last_mut() {
+            Some(w) if (wave(w[0]) - wave(calc)).abs() < adaptive::TOL => {
+                w.push(calc)
+            }
+            _ => waves.push(vec![calc]),
+        }
+    }
+
+    // the failed points and those that haven't been run yet
+    let mut skip: Vec<_> = calcs.iter().map(|c| c.index).collect();
+    let mut time = 0.0;
+    for wave in waves {
+        let ids: Vec<_> = wave.iter().map(|c| c.index).collect();
+        let batch: Vec<_> = wave
+            .into_iter()
+            .map(|calc| {
+                let mut calc = calc.clone();
+                if let Some(geom) =
+                    neighbour_seed(template, points, calc.index, res, &skip)
+                {
+                    calc.geom = geom;
+                }
+                calc
+            })
+            .collect();
+        let failed = match runner.optimize(dir, batch, res, reuse) {
+            Ok(t) => {
+                time += t;
+                Vec::new()
+            }
+            Err(e) => e,
+        };
+        skip.retain(|i| !ids.contains(i) || failed.contains(i));
+    }
+    // by now only the failed points are left
+    if skip.is_empty() {
+        Ok(time)
+    } else {
+        Err(skip)
+    }
+}
+
+/// Return a starting geometry for the optimization of `points[i]` from the
+/// Z-matrix `template`, with the host coordinates taken from the converged
+/// geometry of the nearest point in `res` that is not in `failed`. The probe
+/// is still placed by filling in the grid coordinates, so that it stays in the
+/// frame of the grid and keeps any constraints in `template`.
+fn neighbour_seed(
+    template: &str,
+    points: &[Point],
+    i: usize,
+    res: &[ProgramResult],
+    failed: &[usize],
+) -> Option<Geom> {
+    let dist = |j: usize| {
+        let (p, q) = (points[i].cartesian(), points[j].cartesian());
+        p.iter().zip(q).map(|(p, q)| (p - q).powi(2)).sum::<f64>()
+    };
+    let j = (0..res.len())
+        .filter(|j| !failed.contains(j) && res[*j].cart_geom.is_some())
+        .min_by(|&a, &b| dist(a).total_cmp(&dist(b)))?;
+    let (_, host) = res[j].cart_geom.as_ref()?.split_last()?;
+    let template = seed::seed_host(template, host)?;
+    Some(Geom::Zmat(points[i].fill(&template)))
 }
 
 fn filter_failed<T>(res: Vec<T>, failed_indices: &[usize]) -> Vec<T> {
@@ -94,7 +662,7 @@ fn filter_failed<T>(res: Vec<T>, failed_indices: &[usize]) -> Vec<T> {
 fn first_part(
     config: &FirstPart,
     pts_dir: impl AsRef<Path>,
-    OptOutput { y, z, ref_energy, geom }: OptOutput,
+    OptOutput { point, ref_energy, geom, .. }: OptOutput,
     start_index: usize,
 ) -> BuiltJobs {
     let ref_energy = ref_energy.unwrap();
@@ -114,7 +682,7 @@ fn first_part(
         }
     }
     let pg = mol.point_group();
-    eprintln!("geometry {y:.2} {z:.2}:\n{mol}");
+    debug!("geometry {point}:\n{mol}");
     let mut target_map = BigHash::new(mol.clone(), pg);
     let geoms = Cart.build_points(
         Geom::Xyz(mol.atoms.clone()),
@@ -136,15 +704,14 @@ fn first_part(
                 .join(filename)
                 .to_string_lossy()
                 .to_string();
-            Job::new(
-                Molpro::new(
-                    filename,
-                    template.clone(),
-                    config.charge,
-                    mol.geom,
-                ),
-                mol.index + start_index,
-            )
+            Calc {
+                filename,
+                template: template.clone(),
+                charge: config.charge,
+                geom: mol.geom,
+                index: mol.index + start_index,
+                ghosts: Vec::new(),
+            }
         })
         .collect();
 
@@ -152,40 +719,44 @@ fn first_part(
 }
 
 struct OptInput {
-    y: f64,
-    z: f64,
+    point: Point,
     geometry: Geom,
 }
 
-fn build_opt_inputs(
-    geom_template: &str,
-    yrange: RangeInclusive<isize>,
-    zrange: RangeInclusive<isize>,
-) -> Vec<OptInput> {
-    let mut opt_inputs = Vec::new();
-    // molpro orients a diatomic molecule along the z-axis, so we need to step
-    // He in the yz- (or xz-) plane, with the wider range along z
-    for z in zrange.step_by(2).map(|z| z as f64 / 10.0) {
-        for y in yrange.clone().step_by(2).map(|y| y as f64 / 10.0) {
-            // require {{y}} and {{z}} placeholders in Z-matrix geometry for
-            // positioning the He atom for each calculation
-            let geometry = Geom::Zmat(
-                geom_template
-                    .replace("{{y}}", &y.to_string())
-                    .replace("{{z}}", &z.to_string()),
-            );
-            opt_inputs.push(OptInput { y, z, geometry });
-        }
-    }
-    opt_inputs
+/// Build an [OptInput] for each of `points` from `geom_template`, which should
+/// contain placeholders like `{{y}}` and `{{z}}` or `{{r}}` and `{{theta}}`
+/// for positioning the He atom.
+fn build_opt_inputs(geom_template: &str, points: Vec<Point>) -> Vec<OptInput> {
+    points
+        .into_iter()
+        .map(|point| OptInput {
+            point,
+            geometry: Geom::Zmat(point.fill(geom_template)),
+        })
+        .collect()
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 struct OptOutput {
-    y: f64,
-    z: f64,
+    /// The requested grid point.
+    #[serde(flatten)]
+    point: Point,
     ref_energy: Option<f64>,
     geom: Option<Vec<Atom>>,
+
+    /// The requested Cartesian position of the probe: its position in the
+    /// input geometry if that was given in Cartesian coordinates, or else the
+    /// grid point.
+    #[serde(default)]
+    nominal: Option<[f64; 3]>,
+
+    /// The actual Cartesian position of the probe in the optimized geometry,
+    /// moved into the frame of the grid as for the [Config::freeze_probe]
+    /// check, so that it can be compared to `nominal`. Without a reference
+    /// host geometry to align onto, this is the position in the optimized
+    /// geometry as the program left it.
+    #[serde(default)]
+    probe: Option<[f64; 3]>,
 }
 
 struct BuiltJobs {
@@ -195,12 +766,29 @@ struct BuiltJobs {
     fcs: Vec<f64>,
     mol: Molecule,
     targets: Vec<Target>,
-    jobs: Vec<Job<Molpro>>,
+    jobs: Vec<Calc>,
+}
+
+/// The vibrational frequencies computed for a single grid point.
+#[derive(Clone)]
+struct Freqs {
+    harms: Vec<f64>,
+    corrs: Vec<f64>,
+
+    /// The normal coordinates of each mode, used for tracking modes across the
+    /// grid.
+    modes: Vec<Vec<f64>>,
+
+    /// The geometry that the normal coordinates refer to, as oriented by
+    /// spectro.
+    geom: Vec<Atom>,
+
+    /// The equilibrium rotational constants.
+    rot_consts: Vec<f64>,
 }
 
 struct RunJobs {
-    y: f64,
-    z: f64,
+    point: Point,
     n: usize,
     nfc2: usize,
     nfc3: usize,
@@ -210,6 +798,47 @@ struct RunJobs {
     jobs: Range<usize>,
 }
 
+/// Print the position of the probe relative to the host in each of `opts`,
+/// followed by a blank line to separate it from the frequencies, warning about
+/// any points where the probe is not where it was requested. The optimized
+/// geometries are first moved into the frame of the grid as described in
+/// [probe::align_onto_host], when there is a reference host geometry in
+/// `config`, and otherwise the displacement is left out.
+fn report_probes(config: &Config, opts: &[OptOutput]) {
+    let Some(first) = opts.first() else {
+        return;
+    };
+    println!(
+        "{} {:>8} {:>8} {:>8}",
+        first.point.header(),
+        "dist",
+        "angle",
+        "disp"
+    );
+    let template = geom_template(config);
+    for opt in opts {
+        let Some(geom) = &opt.geom else {
+            continue;
+        };
+        let input = Geom::Zmat(opt.point.fill(template));
+        let aligned = reference_host(config, &input)
+            .zip(opt.nominal)
+            .and_then(|(h, n)| probe::align_onto_host(geom, &h, n));
+        let geom = match &aligned {
+            Some(g) => ProbeGeometry::new(g, opt.nominal),
+            None => ProbeGeometry::new(geom, None),
+        };
+        let Some(geom) = geom else {
+            continue;
+        };
+        println!("{} {geom}", opt.point);
+        if geom.displacement.is_some_and(|d| d > PROBE_TOL) {
+            warn!("probe at grid point ({}) is not where requested", opt.point);
+        }
+    }
+    println!();
+}
+
 /// Serialize `opts` to JSON and save to `path`. Logs any errors, but should
 /// never panic.
 fn write_opt_checkpoint(opts: &Vec<OptOutput>, path: impl AsRef<Path>) {
@@ -231,100 +860,102 @@ fn load_opt_checkpoint(path: impl AsRef<Path>) -> Vec<OptOutput> {
     serde_json::from_str(&s).unwrap()
 }
 
-#[derive(Parser)]
-#[command(author, about, long_about = None)]
-struct Args {
-    #[arg(value_parser, default_value = "pbqff.toml")]
-    config_file: String,
+/// The name of the file in each opt and pts directory describing the jobs run
+/// there, used to check that a restart is resuming the same jobs.
+const LAYOUT_FILE: &str = "layout.json";
 
-    /// Resume from the opt checkpoint file in the current directory.
-    #[arg(short, long, default_value_t = false)]
-    checkpoint: bool,
+/// The name of the checkpoint file that the queue writes in its `check_dir`.
+const CHK_FILE: &str = "chk.json";
 
-    /// Set the maximum number of threads to use. Defaults to 0, which means to
-    /// use as many threads as there are CPUS.
-    #[arg(short, long, default_value_t = 0)]
-    threads: usize,
+/// Serialize `layout` to JSON and save to `path`. Logs any errors, but should
+/// never panic.
+fn write_layout<T: Serialize>(layout: &T, path: impl AsRef<Path>) {
+    match serde_json::to_string(layout) {
+        Ok(s) => {
+            if let Err(e) = std::fs::write(path, s) {
+                eprintln!("error writing job layout: {e:?}");
+            }
+        }
+        Err(e) => {
+            eprintln!("error converting job layout to json: {e:?}");
+        }
+    }
 }
 
-#[derive(Deserialize)]
-struct Config {
-    pbqff: pbqff::config::Config,
-    yrange: RangeInclusive<isize>,
-    zrange: RangeInclusive<isize>,
+/// Load a job layout from the JSON file at `path`, if it exists and can be
+/// parsed.
+fn load_layout<T: DeserializeOwned>(path: impl AsRef<Path>) -> Option<T> {
+    let s = read_to_string(path).ok()?;
+    serde_json::from_str(&s).ok()
 }
 
-impl Config {
-    fn load(path: impl AsRef<Path>) -> Self {
-        let s = read_to_string(path).unwrap();
-        toml::from_str(&s).unwrap()
+/// Report whether `layout`, a description of the jobs about to be run in
+/// `dir`, matches the one recorded there by an interrupted run, if `resume` is
+/// set. Otherwise, or if they don't match, `layout` is recorded for the next
+/// restart.
+fn same_jobs<T>(dir: impl AsRef<Path>, layout: &T, resume: bool) -> bool
+where
+    T: Serialize + DeserializeOwned + PartialEq,
+{
+    let dir = dir.as_ref();
+    let path = dir.join(LAYOUT_FILE);
+    let matched = resume && load_layout(&path).is_some_and(|l: T| l == *layout);
+    if resume && !matched {
+        warn!(
+            "jobs in {} do not match the previous run, starting over",
+            dir.display()
+        );
+    }
+    if !matched {
+        write_layout(layout, &path);
     }
+    matched
 }
 
-/// TODO ensure that the molecule is aligned in the same way on the axis for all
-/// of the He positions (not flipping sign, which would flip the relative He
-/// position). from what I can tell, Molpro is handling this, just verify
-fn main() {
-    env_logger::init();
-
-    let args = Args::parse();
-
-    let config = Config::load(args.config_file);
-    let no_del = false;
-    let work_dir = ".";
-    let opt_dir = "opt";
-    let pts_dir = "pts";
-    info!("initializing thread pool with {} threads", args.threads);
-    max_threads(args.threads);
-
-    let queue = Pbs::new(
-        config.pbqff.chunk_size,
-        config.pbqff.job_limit,
-        config.pbqff.sleep_int,
-        pts_dir,
-        no_del,
-        config.pbqff.queue_template.clone(),
-    );
-
-    info!("cleaning up directories from a previous run");
-    cleanup(work_dir);
-
-    info!("building new directories");
-    std::fs::create_dir(pts_dir).unwrap();
-    std::fs::create_dir(opt_dir).unwrap();
-
-    const OPT_CHK: &str = "opts.json";
+/// Return the Z-matrix geometry template from `config`.
+fn geom_template(config: &Config) -> &str {
+    config
+        .pbqff
+        .geometry
+        .zmat()
+        .expect("griddy requires Z-matrix input")
+}
 
-    let opts = if args.checkpoint {
-        info!("loading optimizations from checkpoint");
-        load_opt_checkpoint(OPT_CHK)
-    } else {
-        let geom_template = config
-            .pbqff
-            .geometry
-            .zmat()
-            .expect("griddy requires Z-matrix input");
-        let opt_inputs =
-            build_opt_inputs(geom_template, config.yrange, config.zrange);
-
-        let template = Template::from(&config.pbqff.template);
-        let opts = optimize(
-            opt_dir,
-            &queue,
-            opt_inputs,
-            template,
-            config.pbqff.charge,
-        );
+/// Build and run the optimizations for each of `points`, resuming an
+/// interrupted run if `resume` is set.
+fn optimize_points(
+    config: &Config,
+    runner: &impl Runner,
+    opt_dir: &str,
+    points: Vec<Point>,
+    resume: bool,
+) -> Vec<OptOutput> {
+    let opt_inputs = build_opt_inputs(geom_template(config), points);
 
-        write_opt_checkpoint(&opts, OPT_CHK);
-        opts
-    };
+    optimize(opt_dir, runner, opt_inputs, config, resume)
+}
 
+/// Build and run the finite-difference jobs for each of `opts`, returning the
+/// frequencies of each grid point whose jobs all succeeded. The queue saves its
+/// progress to a checkpoint in `pts_dir` every `check_int` polls, and if
+/// `resume` is set and the jobs match those of the interrupted run, they are
+/// continued from there instead of starting over. If `results_dir` is
+/// provided, the spectro output for each grid point is written to a
+/// subdirectory of it named after the point, along with the force constants if
+/// [Config::save_fcs] is set.
+fn frequencies(
+    config: &Config,
+    runner: &impl Runner,
+    pts_dir: &str,
+    opts: Vec<OptOutput>,
+    resume: bool,
+    results_dir: Option<&str>,
+) -> Vec<(Point, Freqs)> {
     info!("building jobs from opt output");
     let mut run_jobs = Vec::new();
     let mut all_jobs = Vec::new();
     let mut start_index = 0;
-    for o @ OptOutput { y, z, .. } in opts {
+    for o @ OptOutput { point, .. } in opts {
         let BuiltJobs { n, nfc2, nfc3, fcs, mol, targets, jobs } = first_part(
             &FirstPart::from(config.pbqff.clone()),
             pts_dir,
@@ -336,8 +967,7 @@ fn main() {
         all_jobs.extend(jobs);
         let end = all_jobs.len();
         run_jobs.push(RunJobs {
-            y,
-            z,
+            point,
             n,
             nfc2,
             nfc3,
@@ -348,47 +978,709 @@ fn main() {
         });
     }
 
-    info!("running jobs");
+    let layout: Vec<_> =
+        run_jobs.iter().map(|r| (r.point, r.jobs.clone())).collect();
+    let chk_file = Path::new(pts_dir).join(CHK_FILE);
+    let resume = same_jobs(pts_dir, &layout, resume);
+    if !resume {
+        // don't let a later restart pick up the checkpoint of an older run
+        let _ = std::fs::remove_file(&chk_file);
+    }
 
-    // drain into energies
+    info!("running jobs");
+    let check = match config.pbqff.check_int {
+        0 => Check::None,
+        check_int => Check::Some { check_int, check_dir: pts_dir.to_owned() },
+    };
     let mut energies = vec![0.0; all_jobs.len()];
-    let failed_idxs =
-        match queue.drain(pts_dir, all_jobs, &mut energies, Check::None) {
-            Ok(_) => Vec::new(),
-            Err(e) => e,
-        };
+    let status = if resume {
+        runner.resume(
+            pts_dir,
+            &chk_file,
+            all_jobs.clone(),
+            &mut energies,
+            check,
+        )
+    } else {
+        runner.single_points(pts_dir, all_jobs.clone(), &mut energies, check)
+    };
+    let failed_idxs = match status {
+        Ok(()) => Vec::new(),
+        Err(failed) => config.retry.single_points(
+            runner,
+            pts_dir,
+            &all_jobs,
+            failed,
+            &mut energies,
+        ),
+    };
 
     info!("finished running jobs");
 
-    println!("{:>5} {:>5} {:>8} {:>8}", "y", "z", "harm", "corr");
-
-    for RunJobs { y, z, n, nfc2, nfc3, mut fcs, mut mol, targets, jobs } in
+    let mut results = Vec::new();
+    for RunJobs { point, n, nfc2, nfc3, mut fcs, mut mol, targets, jobs } in
         run_jobs
     {
         if failed_idxs.iter().any(|idx| jobs.contains(idx)) {
-            warn!("skipping grid point ({y}, {z}) with failed jobs");
+            warn!("skipping grid point ({point}) with failed jobs");
             continue;
         }
 
+        let dir = results_dir.map(|d| Path::new(d).join(point.label()));
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+
+        // make_fcs writes fort.15, fort.30, and fort.40 when given a directory
+        let fc_dir = dir.as_ref().filter(|_| config.save_fcs);
         let (fc2, f3, f4) = Cart.make_fcs(
             targets,
             &energies[jobs],
             &mut fcs,
             n,
             Derivative::Quartic(nfc2, nfc3, 0),
-            None::<&str>,
+            fc_dir,
         );
 
         if let Some(d) = &config.pbqff.dummy_atoms {
             mol.atoms.truncate(mol.atoms.len() - d);
         }
 
-        let (spectro, output) = freqs(None::<&str>, &mol, fc2, f3, f4);
-        spectro.write_output(&mut stderr(), &output).unwrap();
+        let (spectro, output) = freqs(dir.as_ref(), &mol, fc2, f3, f4);
+        if let Some(dir) = &dir {
+            let path = dir.join("spectro.out");
+            match File::create(&path) {
+                Ok(mut f) => spectro.write_output(&mut f, &output).unwrap(),
+                Err(e) => {
+                    eprintln!("error writing {}: {e:?}", path.display())
+                }
+            }
+        }
+
+        results.push((
+            point,
+            Freqs {
+                harms: output.harms.iter().copied().collect(),
+                corrs: output.corrs.clone(),
+                modes: (0..output.harms.len())
+                    .map(|i| output.lxm.column(i).iter().copied().collect())
+                    .collect(),
+                geom: spectro.geom.atoms.clone(),
+                rot_consts: spectro.rotcon.clone(),
+            },
+        ));
+    }
+
+    results
+}
+
+/// Return the table header for the harmonic and corrected frequencies of each
+/// of `modes`, matching [mode_columns]. Each label starts with `prefix`.
+fn mode_header(modes: &[usize], prefix: &str) -> String {
+    modes
+        .iter()
+        .map(|m| {
+            let (harm, corr) =
+                (format!("{prefix}harm{m}"), format!("{prefix}corr{m}"));
+            format!(" {harm:>8} {corr:>8}")
+        })
+        .collect()
+}
+
+/// Return the harmonic and corrected frequencies of each of `modes`, numbered
+/// from 1, in `freqs`, or `None` for modes that are not present.
+fn mode_values(freqs: &Freqs, modes: &[usize]) -> Vec<Option<f64>> {
+    let get =
+        |v: &[f64], m: usize| m.checked_sub(1).and_then(|i| v.get(i).copied());
+    modes
+        .iter()
+        .flat_map(|&m| [get(&freqs.harms, m), get(&freqs.corrs, m)])
+        .collect()
+}
+
+/// Format the harmonic and corrected frequencies of each of `modes`, numbered
+/// from 1, in `freqs`. Modes that are not present are printed as `-`.
+fn mode_columns(freqs: &Freqs, modes: &[usize]) -> String {
+    mode_values(freqs, modes)
+        .into_iter()
+        .map(|v| match v {
+            Some(f) => format!(" {f:8.2}"),
+            None => format!(" {:>8}", "-"),
+        })
+        .collect()
+}
+
+impl Freqs {
+    /// Return the shift of each mode in `self` relative to `reference`.
+    fn shift(&self, reference: &Freqs) -> Freqs {
+        let diff = |a: &[f64], b: &[f64]| -> Vec<f64> {
+            a.iter().zip(b).map(|(a, b)| a - b).collect()
+        };
+        Freqs {
+            harms: diff(&self.harms, &reference.harms),
+            corrs: diff(&self.corrs, &reference.corrs),
+            modes: Vec::new(),
+            geom: Vec::new(),
+            rot_consts: Vec::new(),
+        }
+    }
+}
+
+/// Optimize `config.host` on its own and compute its frequencies through the
+/// same pipeline as the grid points, for reporting the shifts caused by the
+/// probe. Returns `None` if any of the calculations fail.
+fn host_frequencies(
+    config: &Config,
+    runner: &impl Runner,
+    opt_dir: &str,
+    pts_dir: &str,
+    resume: bool,
+    results_dir: Option<&str>,
+) -> Option<Freqs> {
+    let geometry = config.host.clone().expect("shifts require a host geometry");
+    let mut host_config = config.clone();
+    host_config.pbqff.geometry = Geom::Zmat(geometry);
+    host_config.pbqff.dummy_atoms = None;
+    // the weights of the probe and any dummy atoms don't apply to the host
+    let nhost = config.host().map_or(0, |m| m.atoms.len());
+    if let Some(ws) = &mut host_config.pbqff.weights {
+        ws.truncate(nhost);
+    }
+    host_config.freeze_probe = false;
+    host_config.seed = false;
+
+    let opt_dir = format!("{opt_dir}/host");
+    let pts_dir = format!("{pts_dir}/host");
+    std::fs::create_dir_all(&opt_dir).unwrap();
+    std::fs::create_dir_all(&pts_dir).unwrap();
+
+    info!("computing the frequencies of the isolated host");
+    // the host has no probe, so none of its atoms should be held fixed
+    let runner = Unfrozen(runner);
+    // the host has no grid point, so use the origin as a placeholder
+    let point = Point::Cartesian { x: 0.0, y: 0.0, z: 0.0 };
+    let opts =
+        optimize_points(&host_config, &runner, &opt_dir, vec![point], resume);
+    let results_dir = results_dir.map(|d| format!("{d}/host"));
+    let results = frequencies(
+        &host_config,
+        &runner,
+        &pts_dir,
+        opts,
+        resume,
+        results_dir.as_deref(),
+    );
+    let ret = results.into_iter().next().map(|(_, f)| f);
+    if ret.is_none() {
+        warn!("failed to compute the frequencies of the isolated host");
+    }
+    ret
+}
+
+/// Compute the interaction energies for `opts` in a subdirectory of `pts_dir`,
+/// if requested in `config`.
+fn interactions(
+    config: &Config,
+    runner: &impl Runner,
+    pts_dir: &str,
+    opts: &[OptOutput],
+) -> Vec<(Point, InteractionEnergy)> {
+    let Some(settings) = &config.interaction else {
+        return Vec::new();
+    };
+    let dir = format!("{pts_dir}/int");
+    std::fs::create_dir_all(&dir).unwrap();
+    interaction::interaction_energies(config, settings, runner, &dir, opts)
+}
+
+/// Return the results for `point` from `results`, if present.
+fn lookup<T>(results: &[(Point, T)], point: Point) -> Option<&T> {
+    results.iter().find(|(p, _)| *p == point).map(|(_, f)| f)
+}
+
+/// Return the points from `points[start..]` that are their own symmetry
+/// representatives in `reps`.
+fn unique_points(points: &[Point], reps: &[usize], start: usize) -> Vec<Point> {
+    (start..points.len())
+        .filter(|&i| reps[i] == i)
+        .map(|i| points[i])
+        .collect()
+}
+
+#[derive(Parser)]
+#[command(author, about, long_about = None)]
+struct Args {
+    #[arg(value_parser, default_value = "pbqff.toml")]
+    config_file: String,
+
+    /// Resume from the opt checkpoint file in the current directory and the
+    /// finite-difference checkpoints in the pts directories, keeping the
+    /// existing opt and pts directories and reusing any complete output files
+    /// in them if they belong to the same jobs.
+    #[arg(short, long, default_value_t = false)]
+    checkpoint: bool,
+
+    /// Set the maximum number of threads to use. Defaults to 0, which means to
+    /// use as many threads as there are CPUS.
+    #[arg(short, long, default_value_t = 0)]
+    threads: usize,
+
+    /// Read an explicit list of probe positions from this CSV or JSON file
+    /// instead of generating the grid from the config file. The coordinates
+    /// are interpreted in the coordinate system of the configured grid,
+    /// defaulting to Cartesian.
+    #[arg(short, long)]
+    points: Option<String>,
+
+    /// A comma-separated list of the modes to print, numbered from 1 in the
+    /// order they are reported by spectro. Defaults to all of the modes.
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    modes: Vec<usize>,
+
+    /// Also write the full results for every grid point, including those that
+    /// failed, to this file, as JSON if it has a `.json` extension and as CSV
+    /// otherwise.
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+struct Config {
+    pbqff: pbqff::config::Config,
+
+    /// The grid of probe positions. Takes precedence over the legacy
+    /// `xrange`, `yrange`, and `zrange` fields.
+    grid: Option<Grid>,
+
+    xrange: Option<Axis>,
+    yrange: Option<Axis>,
+    zrange: Option<Axis>,
+
+    /// Cartesian geometry of the isolated host molecule, in the same
+    /// orientation as the probe grid.
+    host: Option<String>,
+
+    /// Only compute the grid points that are unique under the point group of
+    /// `host`, copying the results to the equivalent points.
+    #[serde(default)]
+    symmetry: bool,
+
+    /// Adaptively refine the grid after the initial pass.
+    adaptive: Option<Adaptive>,
+
+    /// Resubmit failed finite-difference jobs before giving up on their grid
+    /// points.
+    #[serde(default)]
+    retry: Retry,
+
+    /// Resubmit failed optimizations, starting from the host geometry of the
+    /// nearest converged grid point, before giving up on their grid points.
+    #[serde(default)]
+    opt_retry: Retry,
+
+    /// Run the optimizations in waves along the slowest-varying grid
+    /// coordinate, starting each from the converged host geometry of the
+    /// nearest grid point in an earlier wave instead of from the template
+    /// geometry.
+    #[serde(default)]
+    seed: bool,
+
+    /// Require the probe to stay at its grid position in the optimizations,
+    /// discarding any grid points where it moved. The optimized host is first
+    /// superimposed onto the host in the input geometry if it is Cartesian, or
+    /// else onto `host`, so the program may reorient the molecule. The model
+    /// potential holds the probe fixed itself, but optimizers only hold fixed
+    /// the numeric values in a Z-matrix, such as the coordinates filled in
+    /// from the grid, so a Cartesian probe needs explicit constraints in the
+    /// template.
+    #[serde(default)]
+    freeze_probe: bool,
+
+    /// Move the optimized geometries back into the frame of the grid when the
+    /// program has rotated them, instead of just warning about it. The frame
+    /// is found by superimposing the optimized host onto the host in the input
+    /// geometry if it is Cartesian, or else onto `host`.
+    #[serde(default)]
+    canonicalize: bool,
+
+    /// Reorder the modes at each grid point to match the normal coordinates of
+    /// the isolated host if `shifts` is set, or else of the first grid point,
+    /// after rotating them into the same frame, so that each column of output
+    /// refers to the same vibration even where frequencies cross. This also
+    /// happens before adaptive refinement compares neighbouring points, and
+    /// it is always done if `shifts` is set.
+    #[serde(default)]
+    track_modes: bool,
+
+    /// Also compute the frequencies of `host` on its own and print the shift
+    /// of each mode relative to it at every grid point, after matching up the
+    /// modes as described for `track_modes`. The host geometry should list the
+    /// atoms in the same order as the geometry template.
+    #[serde(default)]
+    shifts: bool,
+
+    /// Compute the interaction energy between the host and the probe at each
+    /// grid point.
+    interaction: Option<Interaction>,
+
+    /// Save the force constants for each grid point in the fort.15, fort.30,
+    /// and fort.40 files used by spectro, alongside its output in `results`.
+    #[serde(default)]
+    save_fcs: bool,
+
+    /// Evaluate energies from this analytic model potential instead of
+    /// running the quantum chemistry program.
+    model: Option<Model>,
+}
+
+impl Config {
+    fn load(path: impl AsRef<Path>) -> Self {
+        let s = read_to_string(path).unwrap();
+        toml::from_str(&s).unwrap()
+    }
 
-        println!(
-            "{y:5.2} {z:5.2} {:8.2} {:8.2}",
-            output.harms[0], output.corrs[0]
+    /// Return the [Grid] described by `self`, falling back on a Cartesian grid
+    /// built from the `*range` fields if `grid` is not provided.
+    fn grid(&self) -> Grid {
+        if let Some(grid) = &self.grid {
+            return grid.clone();
+        }
+        let (Some(y), Some(z)) = (&self.yrange, &self.zrange) else {
+            panic!("either grid or yrange and zrange are required");
+        };
+        Grid::Cartesian { x: self.xrange.clone(), y: y.clone(), z: z.clone() }
+    }
+
+    /// Return the coordinate system of the configured grid.
+    fn system(&self) -> System {
+        self.grid.as_ref().map_or(System::Cartesian, Grid::system)
+    }
+
+    /// The in-process runner for the analytic `model`, if present, holding the
+    /// probe fixed if `freeze_probe` is set.
+    fn model_runner(&self) -> Option<InProcess<Model>> {
+        let frozen = usize::from(self.freeze_probe);
+        self.model.clone().map(|m| InProcess::new(m, frozen))
+    }
+
+    /// Parse the `host` geometry, if present.
+    fn host(&self) -> Option<Molecule> {
+        self.host.as_ref().map(|s| s.parse().unwrap())
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    let config = Config::load(&args.config_file);
+    info!("initializing thread pool with {} threads", args.threads);
+    max_threads(args.threads);
+
+    if let Some(runner) = config.model_runner() {
+        info!("using the analytic model potential");
+        return run(&args, &config, runner);
+    }
+
+    match config.pbqff.program {
+        pbqff::config::Program::Molpro => with_queue::<Molpro>(&args, &config),
+        pbqff::config::Program::Mopac => with_queue::<Mopac>(&args, &config),
+        pbqff::config::Program::DFTBPlus => {
+            with_queue::<DFTBPlus>(&args, &config)
+        }
+        pbqff::config::Program::Cfour => with_queue::<Cfour>(&args, &config),
+    }
+}
+
+/// Build the queue requested in `config` and run the whole grid on it with the
+/// quantum chemistry program `P`.
+fn with_queue<P>(args: &Args, config: &Config)
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Pbs: Queue<P>,
+    Slurm: Queue<P>,
+    Local: Queue<P>,
+{
+    let no_del = false;
+    let pts_dir = "pts";
+    let c = &config.pbqff;
+    match c.queue {
+        pbqff::config::Queue::Pbs => run(
+            args,
+            config,
+            Cluster::<P, _>::new(Pbs::new(
+                c.chunk_size,
+                c.job_limit,
+                c.sleep_int,
+                pts_dir,
+                no_del,
+                c.queue_template.clone(),
+            )),
+        ),
+        pbqff::config::Queue::Slurm => run(
+            args,
+            config,
+            Cluster::<P, _>::new(Slurm::new(
+                c.chunk_size,
+                c.job_limit,
+                c.sleep_int,
+                pts_dir,
+                no_del,
+                c.queue_template.clone(),
+            )),
+        ),
+        pbqff::config::Queue::Local => run(
+            args,
+            config,
+            Cluster::<P, _>::new(Local::new(
+                c.chunk_size,
+                c.job_limit,
+                c.sleep_int,
+                pts_dir,
+                no_del,
+                c.queue_template.clone(),
+            )),
+        ),
+    }
+}
+
+/// Run the whole grid with `runner`.
+fn run(args: &Args, config: &Config, runner: impl Runner) {
+    let work_dir = ".";
+    let opt_dir = "opt";
+    let pts_dir = "pts";
+    const OPT_CHK: &str = "opts.json";
+    const RESULTS_DIR: &str = "results";
+
+    if args.checkpoint {
+        info!("keeping directories from a previous run");
+    } else {
+        info!("cleaning up directories from a previous run");
+        cleanup(work_dir);
+        // spectro output from points that aren't rerun would otherwise be
+        // mistaken for part of the new results
+        let _ = std::fs::remove_dir_all(RESULTS_DIR);
+    }
+
+    info!("building new directories");
+    std::fs::create_dir_all(pts_dir).unwrap();
+    std::fs::create_dir_all(opt_dir).unwrap();
+
+    let mut points = match &args.points {
+        Some(path) => {
+            info!("loading grid points from {path}");
+            grid::load_points(path, config.system())
+        }
+        None => config.grid().points(),
+    };
+
+    let symmetry = config.symmetry.then(|| {
+        let host = config.host().expect("symmetry requires a host geometry");
+        let symmetry = Symmetry::new(&host);
+        info!("host symmetry: {symmetry}");
+        symmetry
+    });
+    // index of the symmetry-unique representative of each point
+    let mut reps = symmetry::representatives(&points, symmetry.as_ref());
+    let unique = unique_points(&points, &reps, 0);
+    if config.symmetry {
+        info!(
+            "{} of {} grid points are symmetry-unique",
+            unique.len(),
+            points.len()
+        );
+    }
+
+    let opts = if args.checkpoint && Path::new(OPT_CHK).exists() {
+        info!("loading optimizations from checkpoint");
+        load_opt_checkpoint(OPT_CHK)
+    } else {
+        let opts =
+            optimize_points(config, &runner, opt_dir, unique, args.checkpoint);
+        write_opt_checkpoint(&opts, OPT_CHK);
+        opts
+    };
+    report_probes(config, &opts);
+    let mut energies = interactions(config, &runner, pts_dir, &opts);
+    let mut optimized = opts.clone();
+
+    let host = if config.shifts {
+        host_frequencies(
+            config,
+            &runner,
+            opt_dir,
+            pts_dir,
+            args.checkpoint,
+            Some(RESULTS_DIR),
+        )
+    } else {
+        None
+    };
+
+    let mut results = frequencies(
+        config,
+        &runner,
+        pts_dir,
+        opts,
+        args.checkpoint,
+        Some(RESULTS_DIR),
+    );
+
+    // match up the modes of each batch of results before they are compared
+    // during adaptive refinement
+    let reference = match &host {
+        Some(host) => Some(host.clone()),
+        None => results.first().map(|(_, f)| f.clone()),
+    }
+    .filter(|_| config.track_modes || config.shifts);
+    let track = |results: &mut [(Point, Freqs)]| {
+        if let Some(reference) = &reference {
+            for (_, freqs) in results {
+                freqs.track(reference);
+            }
+        }
+    };
+    track(&mut results);
+
+    if let Some(adaptive) = &config.adaptive {
+        for level in 1..=adaptive.levels {
+            let computed: Vec<_> = points
+                .iter()
+                .zip(&reps)
+                .filter_map(|(&p, &rep)| {
+                    lookup(&results, points[rep]).map(|f| (p, f))
+                })
+                .collect();
+            let new = adaptive::refine(&computed, &points, adaptive.threshold);
+            if new.is_empty() {
+                info!("no more points to refine after level {}", level - 1);
+                break;
+            }
+            info!("refinement level {level}: adding {} points", new.len());
+
+            let start = points.len();
+            points.extend(new);
+            reps = symmetry::representatives(&points, symmetry.as_ref());
+            let unique = unique_points(&points, &reps, start);
+
+            let opt_dir = format!("{opt_dir}/level{level}");
+            let pts_dir = format!("{pts_dir}/level{level}");
+            std::fs::create_dir_all(&opt_dir).unwrap();
+            std::fs::create_dir_all(&pts_dir).unwrap();
+            let opts = optimize_points(
+                config,
+                &runner,
+                &opt_dir,
+                unique,
+                args.checkpoint,
+            );
+            report_probes(config, &opts);
+            energies.extend(interactions(config, &runner, &pts_dir, &opts));
+            optimized.extend_from_slice(&opts);
+            let mut new = frequencies(
+                config,
+                &runner,
+                &pts_dir,
+                opts,
+                args.checkpoint,
+                Some(RESULTS_DIR),
+            );
+            track(&mut new);
+            results.extend(new);
+        }
+    }
+
+    let mut order: Vec<_> = (0..points.len()).collect();
+    if config.adaptive.is_some() {
+        order.sort_by(|&a, &b| adaptive::compare(&points[a], &points[b]));
+    }
+
+    let nmodes = results.first().map_or(0, |(_, f)| f.harms.len());
+    let modes = if args.modes.is_empty() {
+        (1..=nmodes).collect()
+    } else {
+        args.modes.clone()
+    };
+    if let Some(m) = modes.iter().find(|&&m| m > nmodes) {
+        warn!("mode {m} requested, but there are only {nmodes} modes");
+    }
+
+    if let Some(header) = points.first().map(Point::header) {
+        let shifts = match &host {
+            Some(_) => mode_header(&modes, "d"),
+            None => String::new(),
+        };
+        let eint = match &config.interaction {
+            Some(_) => InteractionEnergy::header(),
+            None => String::new(),
+        };
+        println!("{header}{}{shifts}{eint}", mode_header(&modes, ""));
+    }
+
+    let mut lost = Vec::new();
+    let mut records = Vec::new();
+    for i in order {
+        let rep = points[reps[i]];
+        let opt = optimized.iter().find(|o| o.point == rep);
+        let freqs = lookup(&results, rep);
+        let energy = lookup(&energies, rep);
+        let shift = freqs.zip(host.as_ref()).map(|(f, h)| f.shift(h));
+        records.push(Record {
+            point: points[i],
+            status: match (freqs, opt) {
+                (Some(_), _) => Status::Ok,
+                (None, Some(_)) => Status::Skipped,
+                (None, None) => Status::Failed,
+            },
+            ref_energy: opt.and_then(|o| o.ref_energy),
+            // the geometry is only known in the orientation of `rep`
+            geom: opt.filter(|_| reps[i] == i).and_then(|o| o.geom.clone()),
+            representative: (reps[i] != i).then_some(rep),
+            rot_consts: freqs.map_or(Vec::new(), |f| f.rot_consts.clone()),
+            harms: freqs.map_or(Vec::new(), |f| f.harms.clone()),
+            corrs: freqs.map_or(Vec::new(), |f| f.corrs.clone()),
+            dharms: shift.as_ref().map(|s| s.harms.clone()),
+            dcorrs: shift.as_ref().map(|s| s.corrs.clone()),
+            eint: energy.map(|e| e.raw),
+            eint_cp: energy.and_then(|e| e.cp),
+        });
+
+        let Some(freqs) = freqs else {
+            lost.push(points[i]);
+            continue;
+        };
+
+        let shifts = match &shift {
+            Some(shift) => mode_columns(shift, &modes),
+            None => String::new(),
+        };
+        let eint = match (&config.interaction, energy) {
+            (None, _) => String::new(),
+            (Some(_), Some(e)) => e.to_string(),
+            (Some(_), None) => format!(" {:>10} {:>10}", "-", "-"),
+        };
+        println!("{}{}{shifts}{eint}", points[i], mode_columns(freqs, &modes));
+    }
+
+    if let Some(path) = &args.output {
+        info!("writing results to {path}");
+        output::write(&records, path);
+    }
+
+    if !lost.is_empty() {
+        eprintln!(
+            "{} of {} grid points were lost to failed calculations:",
+            lost.len(),
+            points.len()
         );
+        for point in lost {
+            eprintln!("{point}");
+        }
     }
 }
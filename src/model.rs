@@ -0,0 +1,209 @@
+//! Analytic model potential for validating the grid and frequency pipeline
+
+use serde::Deserialize;
+use symm::Atom;
+
+use crate::runner::Potential;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::runner::InProcess;
+    use crate::tests::test_dir;
+    use crate::{frequencies, host_frequencies, optimize_points, Config};
+
+    /// Masses of the most common isotopes of H and O in amu.
+    const MASS_H: f64 = 1.007_825_032_23;
+    const MASS_O: f64 = 15.994_914_619_57;
+
+    /// Reduced mass of the most common isotopes of H and O in amu.
+    pub(crate) const MU_OH: f64 = MASS_H * MASS_O / (MASS_H + MASS_O);
+
+    /// The harmonic frequency of the OH stretch in `geom`, H and O followed by
+    /// a frozen probe, to first order in the probe interaction. This is the
+    /// Morse curvature at the optimized bond length plus the curvature of the
+    /// Lennard-Jones terms along the stretch, with the center of mass of OH
+    /// fixed.
+    fn perturbed_harmonic(model: &Model, geom: &[Atom]) -> f64 {
+        let [h, o, probe] = geom else {
+            panic!("expected H, O, and the probe, got {geom:?}");
+        };
+        let r = distance(h, o);
+        let x = r - model.re;
+        let (e1, e2) = ((-model.a * x).exp(), (-2.0 * model.a * x).exp());
+        let morse = 2.0 * model.de * model.a * model.a * (2.0 * e2 - e1);
+
+        // unit vector along the stretch and the share of it taken by each atom
+        let u = [(h.x - o.x) / r, (h.y - o.y) / r, (h.z - o.z) / r];
+        let m = MASS_H + MASS_O;
+        let mut lj = 0.0;
+        for (atom, c) in [(h, MASS_O / m), (o, -MASS_H / m)] {
+            let d = distance(atom, probe);
+            let s6 = (model.sigma / d).powi(6);
+            let v1 = 4.0 * model.epsilon * (6.0 * s6 - 12.0 * s6 * s6) / d;
+            let v2 =
+                4.0 * model.epsilon * (156.0 * s6 * s6 - 42.0 * s6) / (d * d);
+            let cos = (u[0] * (atom.x - probe.x)
+                + u[1] * (atom.y - probe.y)
+                + u[2] * (atom.z - probe.z))
+                / d;
+            let cos2 = cos * cos;
+            lj += c * c * (v2 * cos2 + v1 / d * (1.0 - cos2));
+        }
+        wavenumber(morse + lj, MU_OH)
+    }
+
+    #[test]
+    fn analytic_frequencies() {
+        let config = Config::load("testfiles/model.toml");
+        let model = config.model.clone().unwrap();
+        let runner = InProcess::new(model.clone(), 1);
+        let points = config.grid().points();
+        let opt_dir = test_dir("griddy_analytic_frequencies_opt");
+        let pts_dir = test_dir("griddy_analytic_frequencies_pts");
+        let opts =
+            optimize_points(&config, &runner, &opt_dir, points.clone(), false);
+        let geoms: Vec<_> =
+            opts.iter().map(|o| o.geom.clone().unwrap()).collect();
+        let got = frequencies(&config, &runner, &pts_dir, opts, false, None);
+        std::fs::remove_dir_all(opt_dir).unwrap();
+        std::fs::remove_dir_all(pts_dir).unwrap();
+        assert_eq!(got.len(), points.len());
+
+        // the first point is far enough away that the probe has no effect,
+        // recovering the exact Morse results
+        let (harm, fund) = (model.harmonic(MU_OH), model.fundamental(MU_OH));
+        let (_, far) = &got[0];
+        assert!(
+            (far.harms[0] - harm).abs() < 0.1,
+            "{} != {harm}",
+            far.harms[0]
+        );
+        assert!(
+            (far.corrs[0] - fund).abs() < 1.0,
+            "{} != {fund}",
+            far.corrs[0]
+        );
+
+        // the next two points are close enough to shift the frequency, by the
+        // curvature of the probe interaction along the bond, and they are
+        // mirror images, so they should shift it identically
+        let (_, a) = &got[1];
+        let (_, b) = &got[2];
+        let want = perturbed_harmonic(&model, &geoms[1]);
+        assert!((want - harm).abs() > 10.0, "{want} is too close to {harm}");
+        assert!((a.harms[0] - want).abs() < 0.5, "{} != {want}", a.harms[0]);
+        assert!((a.harms[0] - b.harms[0]).abs() < 1e-3);
+        assert!((a.corrs[0] - b.corrs[0]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn isolated_host() {
+        let config = Config::load("testfiles/model.toml");
+        let model = config.model.clone().unwrap();
+        // holding the last atom fixed is only for the grid, not the host
+        let runner = InProcess::new(model.clone(), 1);
+        let dir = test_dir("griddy_isolated_host");
+        let got = host_frequencies(&config, &runner, &dir, &dir, false, None)
+            .unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        let harm = model.harmonic(MU_OH);
+        assert!(
+            (got.harms[0] - harm).abs() < 0.1,
+            "{} != {harm}",
+            got.harms[0]
+        );
+    }
+}
+
+/// Conversion factor from Hartree to cm⁻¹.
+pub(crate) const HARTREE_TO_CM: f64 = 219_474.631_363_2;
+
+/// Harmonic wavenumber in cm⁻¹ for a force constant `k` in Hartree/Å² and a
+/// reduced mass `mu` in amu.
+#[cfg(test)]
+pub(crate) fn wavenumber(k: f64, mu: f64) -> f64 {
+    const HARTREE: f64 = 4.359_744_722_2e-18; // J
+    const AMU: f64 = 1.660_539_066_60e-27; // kg
+    const C: f64 = 2.997_924_58e10; // cm/s
+    (k * HARTREE / 1e-20 / (mu * AMU)).sqrt() / (2.0 * std::f64::consts::PI * C)
+}
+
+/// A Morse potential between the first two host atoms, plus a Lennard-Jones
+/// interaction between each probe atom and each host atom, where the probe
+/// atoms are those with the atomic number `probe` and the host is the rest.
+/// Energies are in Hartree and distances in Ångström.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct Model {
+    /// Morse well depth.
+    pub(crate) de: f64,
+
+    /// Morse range parameter in 1/Å.
+    pub(crate) a: f64,
+
+    /// Morse equilibrium bond length.
+    pub(crate) re: f64,
+
+    /// Lennard-Jones well depth. Defaults to 0, turning off the probe.
+    #[serde(default)]
+    pub(crate) epsilon: f64,
+
+    /// Lennard-Jones distance at which the probe interaction vanishes.
+    #[serde(default)]
+    pub(crate) sigma: f64,
+
+    /// The atomic number of the probe. Defaults to 2, for He.
+    #[serde(default = "default_probe")]
+    pub(crate) probe: usize,
+}
+
+fn default_probe() -> usize {
+    2
+}
+
+fn distance(a: &Atom, b: &Atom) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+impl Model {
+    /// The analytic harmonic frequency of the isolated Morse oscillator with
+    /// reduced mass `mu`, in cm⁻¹.
+    pub(crate) fn harmonic(&self, mu: f64) -> f64 {
+        wavenumber(2.0 * self.de * self.a * self.a, mu)
+    }
+
+    /// The analytic fundamental frequency of the isolated Morse oscillator with
+    /// reduced mass `mu`, in cm⁻¹. VPT2 is exact for a Morse oscillator, so
+    /// this is also what spectro should produce.
+    pub(crate) fn fundamental(&self, mu: f64) -> f64 {
+        let we = self.harmonic(mu);
+        let wexe = we * we / (4.0 * self.de * HARTREE_TO_CM);
+        we - 2.0 * wexe
+    }
+}
+
+impl Potential for Model {
+    fn energy(&self, atoms: &[Atom]) -> f64 {
+        let (probes, host): (Vec<_>, Vec<_>) =
+            atoms.iter().partition(|a| a.atomic_number == self.probe);
+        // a host with a single atom has no bond
+        let morse = match host.as_slice() {
+            [a, b, ..] => {
+                let r = distance(a, b);
+                self.de * (1.0 - (-self.a * (r - self.re)).exp()).powi(2)
+            }
+            _ => 0.0,
+        };
+        let mut lj = 0.0;
+        if self.epsilon != 0.0 {
+            for probe in &probes {
+                for atom in &host {
+                    let s6 = (self.sigma / distance(atom, probe)).powi(6);
+                    lj += 4.0 * self.epsilon * (s6 * s6 - s6);
+                }
+            }
+        }
+        morse + lj
+    }
+}
@@ -0,0 +1,169 @@
+//! Tracking vibrational modes across the grid by normal-coordinate overlap
+
+use symm::Atom;
+
+use crate::align::{position, Alignment};
+use crate::Freqs;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swap_crossing_modes() {
+        let reference = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let modes = vec![vec![0.1, -0.99, 0.0], vec![-0.99, 0.1, 0.0]];
+        assert_eq!(assign(&reference, &modes), vec![1, 0]);
+
+        let mut freqs = Freqs {
+            harms: vec![100.0, 200.0],
+            corrs: vec![90.0, 190.0],
+            modes,
+            geom: Vec::new(),
+            rot_consts: Vec::new(),
+        };
+        freqs.reorder(&[1, 0]);
+        assert_eq!(freqs.harms, vec![200.0, 100.0]);
+        assert_eq!(freqs.corrs, vec![190.0, 90.0]);
+        assert_eq!(freqs.modes[0], vec![-0.99, 0.1, 0.0]);
+    }
+
+    #[test]
+    fn track_rotated() {
+        // a bent triatomic in the xz-plane with the oxygen moving along x in
+        // one mode and along y in the other, and the same molecule turned by
+        // 90° about z, with its modes listed in the opposite order
+        let reference = Freqs {
+            harms: vec![1000.0, 2000.0],
+            corrs: vec![990.0, 1990.0],
+            modes: vec![
+                vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                vec![0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            ],
+            geom: vec![
+                Atom::new(8, 0.0, 0.0, 0.0),
+                Atom::new(1, 1.0, 0.0, 0.0),
+                Atom::new(1, 0.0, 0.0, 1.0),
+            ],
+            rot_consts: Vec::new(),
+        };
+        let mut freqs = Freqs {
+            harms: vec![2001.0, 1001.0],
+            corrs: vec![1991.0, 991.0],
+            modes: vec![
+                vec![-1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                vec![0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            ],
+            geom: vec![
+                Atom::new(8, 0.0, 0.0, 0.0),
+                Atom::new(1, 0.0, 1.0, 0.0),
+                Atom::new(1, 0.0, 0.0, 1.0),
+            ],
+            rot_consts: Vec::new(),
+        };
+        // compared directly, the modes seem to be in the same order
+        assert_eq!(assign(&reference.modes, &freqs.modes), vec![0, 1]);
+        freqs.track(&reference);
+        assert_eq!(freqs.harms, vec![1001.0, 2001.0]);
+        assert_eq!(freqs.corrs, vec![991.0, 1991.0]);
+    }
+
+    #[test]
+    fn keep_extra_modes() {
+        let reference = vec![vec![0.0, 1.0]];
+        let modes = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        assert_eq!(assign(&reference, &modes), vec![1, 0]);
+    }
+}
+
+/// Return the magnitude of the overlap between the normal coordinates `a` and
+/// `b`, ignoring their arbitrary signs.
+fn overlap(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(a, b)| a * b).sum();
+    let na: f64 = a.iter().map(|a| a * a).sum();
+    let nb: f64 = b.iter().map(|b| b * b).sum();
+    let norm = (na * nb).sqrt();
+    if norm > 0.0 {
+        dot.abs() / norm
+    } else {
+        0.0
+    }
+}
+
+/// Match each of the `reference` normal coordinates to the one in `modes` it
+/// overlaps with the most, taking the largest overlaps first. Returns the
+/// order in which to take `modes` so that mode `k` corresponds to reference
+/// mode `k`. Any modes without a partner in `reference` are left at the end in
+/// their original order.
+pub(crate) fn assign(reference: &[Vec<f64>], modes: &[Vec<f64>]) -> Vec<usize> {
+    let mut pairs = Vec::new();
+    for (i, r) in reference.iter().enumerate() {
+        for (j, m) in modes.iter().enumerate() {
+            pairs.push((overlap(r, m), i, j));
+        }
+    }
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut order = vec![None; reference.len()];
+    let mut taken = vec![false; modes.len()];
+    for (_, i, j) in pairs {
+        if order[i].is_none() && !taken[j] {
+            order[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+    let mut ret: Vec<_> = order.into_iter().flatten().collect();
+    ret.extend((0..modes.len()).filter(|&j| !taken[j]));
+    ret
+}
+
+/// Return `modes`, the normal coordinates of the atoms in `geom`, rotated into
+/// the frame of `reference` by superimposing the atoms that the two geometries
+/// have in common, which come first in both. The modes are returned unchanged
+/// if the geometries can't be superimposed.
+fn common_frame(
+    modes: &[Vec<f64>],
+    geom: &[Atom],
+    reference: &[Atom],
+) -> Vec<Vec<f64>> {
+    let n = geom.len().min(reference.len());
+    let mobile: Vec<_> = geom[..n].iter().map(position).collect();
+    let target: Vec<_> = reference[..n].iter().map(position).collect();
+    let Some(alignment) = Alignment::new(&mobile, &target, &vec![1.0; n])
+    else {
+        return modes.to_vec();
+    };
+    modes
+        .iter()
+        .map(|mode| {
+            mode.chunks(3)
+                .flat_map(|v| match *v {
+                    [x, y, z] => alignment.vector([x, y, z]).to_vec(),
+                    _ => v.to_vec(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+impl Freqs {
+    /// Reorder the modes in `self` to match those of `reference`, comparing
+    /// their normal coordinates in the frame of the `reference` geometry, since
+    /// spectro reorients each molecule separately.
+    pub(crate) fn track(&mut self, reference: &Freqs) {
+        let modes = common_frame(&self.modes, &self.geom, &reference.geom);
+        let order = assign(&reference.modes, &modes);
+        self.reorder(&order);
+    }
+
+    /// Rearrange the modes in `self` so that mode `k` is the old mode
+    /// `order[k]`.
+    pub(crate) fn reorder(&mut self, order: &[usize]) {
+        fn pick<T: Clone>(v: &[T], order: &[usize]) -> Vec<T> {
+            order.iter().filter_map(|&i| v.get(i).cloned()).collect()
+        }
+        self.harms = pick(&self.harms, order);
+        self.corrs = pick(&self.corrs, order);
+        self.modes = pick(&self.modes, order);
+    }
+}
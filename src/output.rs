@@ -0,0 +1,258 @@
+//! Machine-readable output of the results for each grid point
+
+use std::path::Path;
+
+use serde::Serialize;
+use symm::Atom;
+
+use crate::grid::Point;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                point: Point::Cartesian { x: 0.0, y: 0.0, z: -0.3 },
+                status: Status::Ok,
+                ref_energy: Some(-76.5),
+                geom: Some(vec![
+                    Atom::new(1, 0.0, 0.0, 0.5),
+                    Atom::new(2, 0.0, 0.0, -0.3),
+                ]),
+                representative: None,
+                rot_consts: vec![10.25],
+                harms: vec![3701.5],
+                corrs: vec![3550.0],
+                dharms: Some(vec![-2.5]),
+                dcorrs: Some(vec![-3.0]),
+                eint: None,
+                eint_cp: None,
+            },
+            Record {
+                point: Point::Cartesian { x: 0.0, y: 0.0, z: 0.3 },
+                status: Status::Failed,
+                ref_energy: None,
+                geom: None,
+                representative: None,
+                rot_consts: Vec::new(),
+                harms: Vec::new(),
+                corrs: Vec::new(),
+                dharms: None,
+                dcorrs: None,
+                eint: None,
+                eint_cp: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn csv() {
+        let got = to_csv(&records());
+        let want = "x,y,z,status,ref_energy,geom,rot1,harm1,corr1,dharm1,\
+                    dcorr1\n\
+                    0,0,-0.3,ok,-76.5,1 0 0 0.5; 2 0 0 -0.3,10.25,3701.5,3550,\
+                    -2.5,-3\n\
+                    0,0,0.3,failed,,,,,,,\n";
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn json() {
+        let got: serde_json::Value =
+            serde_json::from_str(&to_json(&records())).unwrap();
+        assert_eq!(got[0]["z"], -0.3);
+        assert_eq!(got[0]["status"], "ok");
+        assert_eq!(got[0]["harms"], serde_json::json!([3701.5]));
+        assert_eq!(got[0]["geom"].as_array().unwrap().len(), 2);
+        assert_eq!(got[1]["status"], "failed");
+        assert_eq!(got[1]["ref_energy"], serde_json::Value::Null);
+        assert_eq!(got[1]["harms"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn representative() {
+        let mut records = records();
+        records[1] = Record {
+            point: Point::Cartesian { x: 0.0, y: 0.0, z: 0.3 },
+            geom: None,
+            representative: Some(records[0].point),
+            ..records[0].clone()
+        };
+        let got = to_csv(&records);
+        let want = "x,y,z,status,ref_energy,geom,representative,rot1,harm1,\
+                    corr1,dharm1,dcorr1\n\
+                    0,0,-0.3,ok,-76.5,1 0 0 0.5; 2 0 0 -0.3,,10.25,3701.5,\
+                    3550,-2.5,-3\n\
+                    0,0,0.3,ok,-76.5,,x0_y0_z-0.3,10.25,3701.5,3550,-2.5,-3\n";
+        assert_eq!(got, want);
+
+        let got: serde_json::Value =
+            serde_json::from_str(&to_json(&records)).unwrap();
+        assert_eq!(got[0]["representative"], serde_json::Value::Null);
+        assert_eq!(got[1]["representative"]["z"], -0.3);
+        assert_eq!(got[1]["geom"], serde_json::Value::Null);
+    }
+}
+
+/// The outcome of the calculations at a grid point.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+    /// The frequencies were computed.
+    Ok,
+
+    /// The optimization failed, or its result was rejected.
+    Failed,
+
+    /// The optimization succeeded, but the frequencies were skipped because
+    /// some of their finite-difference jobs failed.
+    Skipped,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+/// Everything computed for a single grid point. Symmetry-equivalent points
+/// share the results of their representative point, except for the geometry,
+/// which is only known in the orientation of the representative.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Record {
+    #[serde(flatten)]
+    pub(crate) point: Point,
+    pub(crate) status: Status,
+    pub(crate) ref_energy: Option<f64>,
+
+    /// The optimized geometry.
+    pub(crate) geom: Option<Vec<Atom>>,
+
+    /// The symmetry-unique point whose results are shared by this one, if it
+    /// is not its own representative.
+    pub(crate) representative: Option<Point>,
+
+    /// The equilibrium rotational constants from spectro.
+    pub(crate) rot_consts: Vec<f64>,
+    pub(crate) harms: Vec<f64>,
+    pub(crate) corrs: Vec<f64>,
+
+    /// The shifts of the harmonic and corrected frequencies relative to the
+    /// isolated host, if requested.
+    pub(crate) dharms: Option<Vec<f64>>,
+    pub(crate) dcorrs: Option<Vec<f64>>,
+
+    /// The raw and counterpoise-corrected interaction energies in cm⁻¹, if
+    /// requested.
+    pub(crate) eint: Option<f64>,
+    pub(crate) eint_cp: Option<f64>,
+}
+
+/// Format `geom` as a single CSV field, with the atomic number and
+/// coordinates of each atom separated by semicolons.
+fn geom_field(geom: &[Atom]) -> String {
+    let atoms: Vec<_> = geom
+        .iter()
+        .map(|a| format!("{} {} {} {}", a.atomic_number, a.x, a.y, a.z))
+        .collect();
+    atoms.join("; ")
+}
+
+/// Write `records` as CSV, with one column per value. The columns for lists
+/// are numbered from 1 and sized to the longest list across all of
+/// `records`, and optional columns are only included if any of `records` has
+/// a value for them. Missing values are left empty.
+fn to_csv(records: &[Record]) -> String {
+    let Some(first) = records.first() else {
+        return String::new();
+    };
+    let len = |f: fn(&Record) -> usize| records.iter().map(f).max();
+    let nrot = len(|r| r.rot_consts.len()).unwrap_or(0);
+    let nmodes = len(|r| r.harms.len().max(r.corrs.len())).unwrap_or(0);
+    let any = |f: fn(&Record) -> bool| records.iter().any(f);
+    let reps = any(|r| r.representative.is_some());
+    let shifts = any(|r| r.dharms.is_some() || r.dcorrs.is_some());
+    let eint = any(|r| r.eint.is_some() || r.eint_cp.is_some());
+
+    let mut columns: Vec<_> = first
+        .point
+        .coords()
+        .into_iter()
+        .map(|(name, _)| name.to_owned())
+        .collect();
+    columns.extend(["status", "ref_energy", "geom"].map(str::to_owned));
+    if reps {
+        columns.push("representative".to_owned());
+    }
+    let numbered = |name: &str, n: usize| -> Vec<String> {
+        (1..=n).map(|i| format!("{name}{i}")).collect()
+    };
+    columns.extend(numbered("rot", nrot));
+    columns.extend(numbered("harm", nmodes));
+    columns.extend(numbered("corr", nmodes));
+    if shifts {
+        columns.extend(numbered("dharm", nmodes));
+        columns.extend(numbered("dcorr", nmodes));
+    }
+    if eint {
+        columns.extend(["eint", "eint_cp"].map(str::to_owned));
+    }
+
+    let mut ret = columns.join(",");
+    ret.push('\n');
+    for r in records {
+        let opt = |v: Option<f64>| v.map_or(String::new(), |v| v.to_string());
+        let list = |v: &[f64], n: usize| -> Vec<String> {
+            (0..n).map(|i| opt(v.get(i).copied())).collect()
+        };
+        let mut fields: Vec<_> = r
+            .point
+            .coords()
+            .into_iter()
+            .map(|(_, v)| v.to_string())
+            .collect();
+        fields.push(r.status.as_str().to_owned());
+        fields.push(opt(r.ref_energy));
+        fields.push(r.geom.as_deref().map_or(String::new(), geom_field));
+        if reps {
+            fields.push(r.representative.map_or(String::new(), |p| p.label()));
+        }
+        fields.extend(list(&r.rot_consts, nrot));
+        fields.extend(list(&r.harms, nmodes));
+        fields.extend(list(&r.corrs, nmodes));
+        if shifts {
+            fields.extend(list(r.dharms.as_deref().unwrap_or(&[]), nmodes));
+            fields.extend(list(r.dcorrs.as_deref().unwrap_or(&[]), nmodes));
+        }
+        if eint {
+            fields.extend([opt(r.eint), opt(r.eint_cp)]);
+        }
+        ret.push_str(&fields.join(","));
+        ret.push('\n');
+    }
+    ret
+}
+
+fn to_json(records: &[Record]) -> String {
+    serde_json::to_string_pretty(records).unwrap()
+}
+
+/// Write `records` to `path` as JSON if its extension is `json` and as CSV
+/// otherwise. Logs any errors, but should never panic.
+pub(crate) fn write(records: &[Record], path: impl AsRef<Path>) {
+    let path = path.as_ref();
+    let s = if path.extension().is_some_and(|ext| ext == "json") {
+        to_json(records)
+    } else {
+        to_csv(records)
+    };
+    if let Err(e) = std::fs::write(path, s) {
+        eprintln!("error writing output to {}: {e:?}", path.display());
+    }
+}
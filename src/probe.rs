@@ -0,0 +1,157 @@
+//! Measurements of the optimized probe position relative to the host
+
+use std::fmt::Display;
+
+use symm::Atom;
+
+use crate::align::{position, Alignment};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measure_linear() {
+        let geom = vec![
+            Atom::new(1, 0.0, 0.0, -0.5),
+            Atom::new(8, 0.0, 0.0, 0.5),
+            Atom::new(2, 0.0, 3.0, 3.0),
+        ];
+        let got = ProbeGeometry::new(&geom, Some([0.0, 3.0, 2.9])).unwrap();
+        assert!((got.distance - 18.0_f64.sqrt()).abs() < 1e-12);
+        assert!((got.angle - 45.0).abs() < 1e-12);
+        assert!((got.displacement.unwrap() - 0.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn align_reoriented() {
+        // the host has been turned to lie along the x-axis and moved, with the
+        // probe 0.1 Å further out than requested
+        let geom = vec![
+            Atom::new(1, 1.5, 2.0, 0.0),
+            Atom::new(8, 2.5, 2.0, 0.0),
+            Atom::new(2, 5.1, 2.0, 0.0),
+        ];
+        let host =
+            vec![Atom::new(1, 0.0, 0.0, -0.5), Atom::new(8, 0.0, 0.0, 0.5)];
+        let nominal = [0.0, 0.0, 3.0];
+        let got = align_onto_host(&geom, &host, nominal).unwrap();
+        let p = &got[2];
+        assert!(p.x.abs() < 1e-6 && p.y.abs() < 1e-6, "{p:?}");
+        assert!((p.z - 3.1).abs() < 1e-6, "{p:?}");
+        let got = ProbeGeometry::new(&got, Some(nominal)).unwrap();
+        assert!((got.distance - 3.1).abs() < 1e-6);
+        assert!(got.angle.abs() < 1e-4);
+        assert!((got.displacement.unwrap() - 0.1).abs() < 1e-6);
+        assert!(align_onto_host(&geom, &host[..1], nominal).is_none());
+    }
+
+    #[test]
+    fn measure_no_host() {
+        let geom = vec![Atom::new(2, 0.0, 0.0, 1.0)];
+        assert!(ProbeGeometry::new(&geom, None).is_none());
+    }
+}
+
+/// The position of the probe, the last atom in an optimized geometry,
+/// relative to the rest of the atoms, the host.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ProbeGeometry {
+    /// Distance in Å from the centroid of the host to the probe.
+    pub(crate) distance: f64,
+
+    /// Angle in degrees between the z-axis and the vector from the centroid of
+    /// the host to the probe, matching the polar angle of the grid.
+    pub(crate) angle: f64,
+
+    /// Distance in Å from the requested probe position to the actual one, if
+    /// the requested position is known.
+    pub(crate) displacement: Option<f64>,
+}
+
+/// The weight of the probe when superimposing an optimized geometry onto the
+/// reference host. It is only there to fix any rotation about the axis of a
+/// linear host, so it should barely affect the alignment of the host itself.
+const PROBE_WEIGHT: f64 = 1e-6;
+
+/// Find the [Alignment] that moves `geom` into the frame of `host`, the
+/// reference host geometry that the grid is defined around, by superimposing
+/// all but the last atom of `geom`, the probe, onto it. `nominal` is the
+/// requested position of the probe in the frame of `host`. This undoes any
+/// reorientation of the molecule by the quantum chemistry program. Returns
+/// `None` if the host atoms of `geom` don't match `host`.
+pub(crate) fn host_alignment(
+    geom: &[Atom],
+    host: &[Atom],
+    nominal: [f64; 3],
+) -> Option<Alignment> {
+    let (_, rest) = geom.split_last()?;
+    if host.is_empty()
+        || rest.len() != host.len()
+        || rest
+            .iter()
+            .zip(host)
+            .any(|(a, b)| a.atomic_number != b.atomic_number)
+    {
+        return None;
+    }
+    let mobile: Vec<_> = geom.iter().map(position).collect();
+    let target: Vec<_> = host.iter().map(position).chain([nominal]).collect();
+    let mut weights = vec![1.0; host.len()];
+    weights.push(PROBE_WEIGHT);
+    Alignment::new(&mobile, &target, &weights)
+}
+
+/// Return a copy of `geom` moved into the frame of `host` as described in
+/// [host_alignment].
+pub(crate) fn align_onto_host(
+    geom: &[Atom],
+    host: &[Atom],
+    nominal: [f64; 3],
+) -> Option<Vec<Atom>> {
+    host_alignment(geom, host, nominal).map(|a| a.atoms(geom))
+}
+
+fn norm(v: [f64; 3]) -> f64 {
+    v.iter().map(|x| x * x).sum::<f64>().sqrt()
+}
+
+impl ProbeGeometry {
+    /// Measure the probe position in `geom`, where `nominal` is the position
+    /// requested for it in the input geometry. Returns `None` if `geom` has no
+    /// host atoms.
+    pub(crate) fn new(
+        geom: &[Atom],
+        nominal: Option<[f64; 3]>,
+    ) -> Option<Self> {
+        let (probe, host) = geom.split_last()?;
+        if host.is_empty() {
+            return None;
+        }
+        let n = host.len() as f64;
+        let c = host.iter().fold([0.0; 3], |[x, y, z], a| {
+            [x + a.x / n, y + a.y / n, z + a.z / n]
+        });
+        let p = [probe.x, probe.y, probe.z];
+        let r = [p[0] - c[0], p[1] - c[1], p[2] - c[2]];
+        let distance = norm(r);
+        let angle = if distance > 0.0 {
+            (r[2] / distance).clamp(-1.0, 1.0).acos().to_degrees()
+        } else {
+            0.0
+        };
+        let displacement =
+            nominal.map(|[x, y, z]| norm([p[0] - x, p[1] - y, p[2] - z]));
+        Some(Self { distance, angle, displacement })
+    }
+}
+
+impl Display for ProbeGeometry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:8.4} {:8.2} ", self.distance, self.angle)?;
+        match self.displacement {
+            Some(d) => write!(f, "{d:8.4}"),
+            None => write!(f, "{:>8}", "-"),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Resubmission of failed calculations
+
+use log::{info, warn};
+use psqs::geom::Geom;
+use psqs::program::{ProgramResult, Template};
+use psqs::queue::Check;
+use serde::Deserialize;
+
+use crate::runner::{Calc, Runner};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A [Runner] whose single points fail until they have been retried
+    /// `needed` times, recording the templates it was given. Its optimizations
+    /// always fail.
+    struct Flaky {
+        needed: usize,
+        templates: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl Runner for Flaky {
+        fn optimize(
+            &self,
+            _dir: &str,
+            calcs: Vec<Calc>,
+            _dst: &mut [ProgramResult],
+            _reuse: bool,
+        ) -> Result<f64, Vec<usize>> {
+            Err(calcs.iter().map(|c| c.index).collect())
+        }
+
+        fn single_points(
+            &self,
+            _dir: &str,
+            calcs: Vec<Calc>,
+            dst: &mut [f64],
+            _check: Check,
+        ) -> Result<(), Vec<usize>> {
+            let mut failed = Vec::new();
+            for calc in calcs {
+                self.templates
+                    .lock()
+                    .unwrap()
+                    .push(calc.template.header.clone());
+                if calc.filename.ends_with(&format!(".r{}", self.needed)) {
+                    dst[calc.index] = 1.0;
+                } else {
+                    failed.push(calc.index);
+                }
+            }
+            if failed.is_empty() {
+                Ok(())
+            } else {
+                Err(failed)
+            }
+        }
+    }
+
+    fn calcs() -> Vec<Calc> {
+        (0..3)
+            .map(|index| Calc {
+                filename: format!("job.{index:08}"),
+                template: Template::from("orig"),
+                charge: 0,
+                geom: Geom::Zmat(String::new()),
+                index,
+                ghosts: Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retry_single_points() {
+        let runner = Flaky { needed: 2, templates: Default::default() };
+        let retry = Retry { attempts: 2, template: Some("loose".to_owned()) };
+        let mut dst = vec![0.0; 3];
+        let got =
+            retry.single_points(&runner, "pts", &calcs(), vec![1], &mut dst);
+        assert!(got.is_empty());
+        assert_eq!(dst, vec![0.0, 1.0, 0.0]);
+        assert_eq!(*runner.templates.lock().unwrap(), vec!["loose", "loose"]);
+    }
+
+    #[test]
+    fn give_up() {
+        let runner = Flaky { needed: 3, templates: Default::default() };
+        let retry = Retry { attempts: 2, template: None };
+        let mut dst = vec![0.0; 3];
+        let got =
+            retry.single_points(&runner, "pts", &calcs(), vec![0, 2], &mut dst);
+        assert_eq!(got, vec![0, 2]);
+        assert_eq!(runner.templates.lock().unwrap().len(), 4);
+    }
+}
+
+/// Settings for resubmitting failed calculations.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct Retry {
+    /// The number of times to resubmit a failed calculation before giving up.
+    pub(crate) attempts: usize,
+
+    /// An alternative template for the resubmitted calculations, such as one
+    /// with looser convergence thresholds or a different initial guess.
+    /// Defaults to the original template.
+    pub(crate) template: Option<String>,
+}
+
+impl Retry {
+    /// Return copies of the members of `calcs` with indices in `failed`, set up
+    /// for retry number `attempt`. The files are renamed so that they don't
+    /// collide with those of earlier attempts.
+    fn prepare(
+        &self,
+        calcs: &[Calc],
+        failed: &[usize],
+        attempt: usize,
+    ) -> Vec<Calc> {
+        calcs
+            .iter()
+            .filter(|c| failed.contains(&c.index))
+            .map(|c| {
+                let mut c = c.clone();
+                c.filename = format!("{}.r{attempt}", c.filename);
+                if let Some(t) = &self.template {
+                    c.template = Template::from(t);
+                }
+                c
+            })
+            .collect()
+    }
+
+    /// Resubmit the members of `calcs` with indices in `failed` to `runner` up
+    /// to `self.attempts` times, storing the results in `dst`. Returns the
+    /// indices of the calculations that still failed.
+    pub(crate) fn single_points(
+        &self,
+        runner: &impl Runner,
+        dir: &str,
+        calcs: &[Calc],
+        mut failed: Vec<usize>,
+        dst: &mut [f64],
+    ) -> Vec<usize> {
+        for attempt in 1..=self.attempts {
+            if failed.is_empty() {
+                break;
+            }
+            info!("retry {attempt}: resubmitting {} failed jobs", failed.len());
+            let retries = self.prepare(calcs, &failed, attempt);
+            failed = match runner.single_points(dir, retries, dst, Check::None)
+            {
+                Ok(()) => Vec::new(),
+                Err(e) => e,
+            };
+        }
+        if !failed.is_empty() && self.attempts > 0 {
+            warn!(
+                "{} jobs failed after {} retries",
+                failed.len(),
+                self.attempts
+            );
+        }
+        failed
+    }
+
+    /// Like [Retry::single_points], but for optimizations. Before each attempt,
+    /// `seed` is called with the index of each failed calculation, the
+    /// current results, and the indices that have failed so far, and it can
+    /// return a new starting geometry for the calculation, such as the
+    /// host geometry of a neighbouring grid point.
+    pub(crate) fn optimize(
+        &self,
+        runner: &impl Runner,
+        dir: &str,
+        calcs: &[Calc],
+        mut failed: Vec<usize>,
+        dst: &mut [ProgramResult],
+        seed: impl Fn(usize, &[ProgramResult], &[usize]) -> Option<Geom>,
+    ) -> Vec<usize> {
+        for attempt in 1..=self.attempts {
+            if failed.is_empty() {
+                break;
+            }
+            info!(
+                "retry {attempt}: resubmitting {} failed optimizations",
+                failed.len()
+            );
+            let mut retries = self.prepare(calcs, &failed, attempt);
+            for calc in &mut retries {
+                if let Some(geom) = seed(calc.index, dst, &failed) {
+                    calc.geom = geom;
+                }
+            }
+            failed = match runner.optimize(dir, retries, dst, false) {
+                Ok(_) => Vec::new(),
+                Err(e) => e,
+            };
+        }
+        if !failed.is_empty() && self.attempts > 0 {
+            warn!(
+                "{} optimizations failed after {} retries",
+                failed.len(),
+                self.attempts
+            );
+        }
+        failed
+    }
+}
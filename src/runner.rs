@@ -0,0 +1,517 @@
+//! Backends for running the optimizations and single-point energies
+
+use std::marker::PhantomData;
+use std::path::Path;
+
+use log::info;
+use psqs::geom::Geom;
+use psqs::program::{Job, Program, ProgramResult, Template};
+use psqs::queue::{Check, Queue};
+use serde::{Deserialize, Serialize};
+use symm::{Atom, Molecule};
+
+/// A single calculation to be run by a [Runner].
+#[derive(Clone)]
+pub(crate) struct Calc {
+    /// The base name of the input and output files.
+    pub(crate) filename: String,
+    pub(crate) template: Template,
+    pub(crate) charge: isize,
+    pub(crate) geom: Geom,
+    /// The index of the result in the output slice.
+    pub(crate) index: usize,
+    /// The indices of atoms in `geom`, in increasing order, to treat as ghost
+    /// atoms, which carry basis functions but no nuclei or electrons. Any
+    /// `{{ghosts}}` in the template is replaced by a comma-separated list of
+    /// their positions in `geom`, counting from 1.
+    pub(crate) ghosts: Vec<usize>,
+}
+
+impl Calc {
+    fn into_job<P: Program>(self) -> Job<P> {
+        let template = if self.ghosts.is_empty() {
+            self.template
+        } else {
+            let ghosts: Vec<_> =
+                self.ghosts.iter().map(|g| (g + 1).to_string()).collect();
+            Template::from(
+                &self
+                    .template
+                    .header
+                    .replace("{{ghosts}}", &ghosts.join(",")),
+            )
+        };
+        Job::new(
+            P::new(self.filename, template, self.charge, self.geom),
+            self.index,
+        )
+    }
+}
+
+/// A way of running batches of [Calc]s. Both methods return the indices of
+/// any calculations that failed as their error value.
+pub(crate) trait Runner {
+    /// Optimize the geometry of each of `calcs`, storing the results in `dst`.
+    /// If `reuse` is set, the results of an interrupted run of the same
+    /// `calcs` are read back where possible instead of running them again. On
+    /// success, returns the total run time in seconds.
+    fn optimize(
+        &self,
+        dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [ProgramResult],
+        reuse: bool,
+    ) -> Result<f64, Vec<usize>>;
+
+    /// Like [Runner::optimize], but without holding any atoms fixed, for
+    /// molecules without a probe, such as the isolated host. The default just
+    /// calls [Runner::optimize], for runners like [Cluster] that leave any
+    /// constraints to the template.
+    fn optimize_unfrozen(
+        &self,
+        dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [ProgramResult],
+        reuse: bool,
+    ) -> Result<f64, Vec<usize>> {
+        self.optimize(dir, calcs, dst, reuse)
+    }
+
+    /// Compute the single-point energy of each of `calcs`, storing the results
+    /// in `dst`. Progress is saved to a checkpoint as requested by `check`.
+    fn single_points(
+        &self,
+        dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [f64],
+        check: Check,
+    ) -> Result<(), Vec<usize>>;
+
+    /// Like [Runner::single_points], but continuing an interrupted run of the
+    /// same `calcs` from the `checkpoint` it wrote, or from any complete output
+    /// files if it didn't get that far. The default just runs all of `calcs`
+    /// again, for runners that never write a checkpoint.
+    fn resume(
+        &self,
+        dir: &str,
+        checkpoint: &Path,
+        calcs: Vec<Calc>,
+        dst: &mut [f64],
+        check: Check,
+    ) -> Result<(), Vec<usize>> {
+        let _ = checkpoint;
+        self.single_points(dir, calcs, dst, check)
+    }
+}
+
+/// A [Runner] that runs the optimizations of another with
+/// [Runner::optimize_unfrozen].
+pub(crate) struct Unfrozen<'a, R>(pub(crate) &'a R);
+
+impl<R: Runner> Runner for Unfrozen<'_, R> {
+    fn optimize(
+        &self,
+        dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [ProgramResult],
+        reuse: bool,
+    ) -> Result<f64, Vec<usize>> {
+        self.0.optimize_unfrozen(dir, calcs, dst, reuse)
+    }
+
+    fn single_points(
+        &self,
+        dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [f64],
+        check: Check,
+    ) -> Result<(), Vec<usize>> {
+        self.0.single_points(dir, calcs, dst, check)
+    }
+
+    fn resume(
+        &self,
+        dir: &str,
+        checkpoint: &Path,
+        calcs: Vec<Calc>,
+        dst: &mut [f64],
+        check: Check,
+    ) -> Result<(), Vec<usize>> {
+        self.0.resume(dir, checkpoint, calcs, dst, check)
+    }
+}
+
+/// A [Runner] that submits the quantum chemistry program `P` to the queue `Q`.
+pub(crate) struct Cluster<P, Q> {
+    queue: Q,
+    program: PhantomData<P>,
+}
+
+impl<P, Q> Cluster<P, Q> {
+    pub(crate) fn new(queue: Q) -> Self {
+        Self { queue, program: PhantomData }
+    }
+}
+
+impl<P: Program, Q> Cluster<P, Q> {
+    /// Split `calcs` into those that still need to be run and the results of
+    /// those with complete output files. The output files are not checked
+    /// against `calcs`, so this is only valid when resuming the same
+    /// calculations.
+    fn partition(
+        &self,
+        calcs: Vec<Calc>,
+    ) -> (Vec<Calc>, Vec<(usize, ProgramResult)>) {
+        let mut todo = Vec::new();
+        let mut done = Vec::new();
+        for calc in calcs {
+            match P::read_output(&calc.filename) {
+                Ok(res) => done.push((calc.index, res)),
+                Err(_) => todo.push(calc),
+            }
+        }
+        if !done.is_empty() {
+            info!("reusing {} existing output files", done.len());
+        }
+        (todo, done)
+    }
+}
+
+impl<P, Q> Runner for Cluster<P, Q>
+where
+    P: Program + Clone + Send + Sync + Serialize + for<'a> Deserialize<'a>,
+    Q: Queue<P> + Sync,
+{
+    fn optimize(
+        &self,
+        dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [ProgramResult],
+        reuse: bool,
+    ) -> Result<f64, Vec<usize>> {
+        let calcs = if reuse {
+            let (calcs, done) = self.partition(calcs);
+            for (index, res) in done {
+                dst[index] = res;
+            }
+            calcs
+        } else {
+            calcs
+        };
+        if calcs.is_empty() {
+            return Ok(0.0);
+        }
+        let jobs = calcs.into_iter().map(Calc::into_job).collect();
+        self.queue.energize(dir, jobs, dst)
+    }
+
+    fn single_points(
+        &self,
+        dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [f64],
+        check: Check,
+    ) -> Result<(), Vec<usize>> {
+        if calcs.is_empty() {
+            return Ok(());
+        }
+        let jobs = calcs.into_iter().map(Calc::into_job).collect();
+        self.queue.drain(dir, jobs, dst, check).map(|_| ())
+    }
+
+    fn resume(
+        &self,
+        dir: &str,
+        checkpoint: &Path,
+        calcs: Vec<Calc>,
+        dst: &mut [f64],
+        check: Check,
+    ) -> Result<(), Vec<usize>> {
+        // interrupted before the first checkpoint was written
+        if !checkpoint.exists() {
+            let (calcs, done) = self.partition(calcs);
+            for (index, res) in done {
+                dst[index] = res.energy;
+            }
+            return self.single_points(dir, calcs, dst, check);
+        }
+        info!("resuming jobs from {}", checkpoint.display());
+        self.queue.resume(dir, checkpoint, dst, check).map(|_| ())
+    }
+}
+
+/// Return the atoms in `geom` if it is in Cartesian coordinates, including a
+/// "Z-matrix" containing only Cartesian lines.
+pub(crate) fn cartesian(geom: &Geom) -> Option<Vec<Atom>> {
+    match geom {
+        Geom::Xyz(atoms) => Some(atoms.clone()),
+        Geom::Zmat(s) => {
+            let s: Vec<_> =
+                s.lines().filter(|l| !l.trim().is_empty()).collect();
+            let mol: Molecule = s.join("\n").parse().ok()?;
+            Some(mol.atoms)
+        }
+    }
+}
+
+/// A model potential energy surface for use with [InProcess].
+pub(crate) trait Potential: Sync {
+    /// Return the energy in Hartree of `atoms`, whose coordinates are in
+    /// Ångström.
+    fn energy(&self, atoms: &[Atom]) -> f64;
+}
+
+/// A [Runner] that evaluates a [Potential] directly instead of calling an
+/// external program, for testing the whole pipeline without a queue. Only
+/// Cartesian geometries are supported, but these may come from a "Z-matrix"
+/// template containing only Cartesian lines.
+pub(crate) struct InProcess<V> {
+    potential: V,
+
+    /// The number of trailing atoms, such as the probe, to hold fixed in
+    /// optimizations.
+    frozen: usize,
+}
+
+/// Convergence threshold on the largest gradient component in Hartree/Å.
+const GTOL: f64 = 1e-8;
+
+/// Step size in Å for numerical gradients.
+const GRAD_STEP: f64 = 1e-5;
+
+const MAX_ITER: usize = 500;
+
+impl<V: Potential> InProcess<V> {
+    pub(crate) fn new(potential: V, frozen: usize) -> Self {
+        Self { potential, frozen }
+    }
+
+    /// The energy of `atoms` with the free coordinates replaced by `x`.
+    fn energy_at(&self, atoms: &mut [Atom], x: &[f64]) -> f64 {
+        for (atom, c) in atoms.iter_mut().zip(x.chunks(3)) {
+            (atom.x, atom.y, atom.z) = (c[0], c[1], c[2]);
+        }
+        self.potential.energy(atoms)
+    }
+
+    /// Numerical gradient with respect to the free coordinates `x`, with the
+    /// rigid translations and rotations of the free atoms projected out so
+    /// that they keep their position relative to the frozen ones.
+    fn gradient(&self, atoms: &mut [Atom], x: &[f64]) -> Vec<f64> {
+        let mut g = Vec::with_capacity(x.len());
+        let mut y = x.to_vec();
+        for (i, &xi) in x.iter().enumerate() {
+            y[i] = xi + GRAD_STEP;
+            let fp = self.energy_at(atoms, &y);
+            y[i] = xi - GRAD_STEP;
+            let fm = self.energy_at(atoms, &y);
+            y[i] = xi;
+            g.push((fp - fm) / (2.0 * GRAD_STEP));
+        }
+        for b in rigid_basis(x) {
+            let d = dot(&g, &b);
+            for (gi, bi) in g.iter_mut().zip(&b) {
+                *gi -= d * bi;
+            }
+        }
+        g
+    }
+
+    /// Minimize the energy of `atoms` with a BFGS optimizer, holding the last
+    /// `frozen` atoms fixed. Returns `None` if the optimization fails to
+    /// converge.
+    fn minimize(
+        &self,
+        mut atoms: Vec<Atom>,
+        frozen: usize,
+    ) -> Option<(f64, Vec<Atom>)> {
+        let nfree = atoms.len().saturating_sub(frozen);
+        let mut x: Vec<_> = atoms[..nfree]
+            .iter()
+            .flat_map(|a| [a.x, a.y, a.z])
+            .collect();
+        let n = x.len();
+        // inverse Hessian approximation
+        let mut h = vec![0.0; n * n];
+        for i in 0..n {
+            h[i * n + i] = 1.0;
+        }
+        let mut e = self.energy_at(&mut atoms, &x);
+        let mut g = self.gradient(&mut atoms, &x);
+        for _ in 0..MAX_ITER {
+            if g.iter().all(|gi| gi.abs() < GTOL) {
+                self.energy_at(&mut atoms, &x);
+                return Some((e, atoms));
+            }
+            let p: Vec<_> = h.chunks(n).map(|row| -dot(row, &g)).collect();
+            let slope = dot(&g, &p);
+            // backtracking line search for the Armijo condition, allowing for
+            // some noise in the energy near convergence
+            let mut t = 1.0;
+            let (xn, en) = loop {
+                let xn: Vec<_> =
+                    x.iter().zip(&p).map(|(xi, pi)| xi + t * pi).collect();
+                let en = self.energy_at(&mut atoms, &xn);
+                if en <= e + 1e-4 * t * slope + 1e-13 {
+                    break (xn, en);
+                }
+                t *= 0.5;
+                if t < 1e-12 {
+                    return None;
+                }
+            };
+            let gn = self.gradient(&mut atoms, &xn);
+            let s: Vec<_> = xn.iter().zip(&x).map(|(a, b)| a - b).collect();
+            let y: Vec<_> = gn.iter().zip(&g).map(|(a, b)| a - b).collect();
+            let sy = dot(&s, &y);
+            if sy > 1e-14 {
+                bfgs_update(&mut h, &s, &y, sy);
+            }
+            (x, e, g) = (xn, en, gn);
+        }
+        None
+    }
+
+    /// Optimize each of `calcs` with the last `frozen` atoms held fixed.
+    fn optimize_frozen(
+        &self,
+        calcs: Vec<Calc>,
+        dst: &mut [ProgramResult],
+        frozen: usize,
+    ) -> Result<f64, Vec<usize>> {
+        let mut failed = Vec::new();
+        for calc in calcs {
+            match cartesian(&calc.geom).and_then(|a| self.minimize(a, frozen)) {
+                Some((energy, atoms)) => {
+                    dst[calc.index] = ProgramResult {
+                        energy,
+                        cart_geom: Some(atoms),
+                        ..Default::default()
+                    };
+                }
+                None => failed.push(calc.index),
+            }
+        }
+        if failed.is_empty() {
+            Ok(0.0)
+        } else {
+            Err(failed)
+        }
+    }
+}
+
+impl<V: Potential> Runner for InProcess<V> {
+    fn optimize(
+        &self,
+        _dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [ProgramResult],
+        _reuse: bool,
+    ) -> Result<f64, Vec<usize>> {
+        self.optimize_frozen(calcs, dst, self.frozen)
+    }
+
+    fn optimize_unfrozen(
+        &self,
+        _dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [ProgramResult],
+        _reuse: bool,
+    ) -> Result<f64, Vec<usize>> {
+        self.optimize_frozen(calcs, dst, 0)
+    }
+
+    fn single_points(
+        &self,
+        _dir: &str,
+        calcs: Vec<Calc>,
+        dst: &mut [f64],
+        _check: Check,
+    ) -> Result<(), Vec<usize>> {
+        let mut failed = Vec::new();
+        for calc in calcs {
+            match cartesian(&calc.geom) {
+                // a model potential has no basis set, so ghost atoms are just
+                // left out
+                Some(mut atoms) => {
+                    for &g in calc.ghosts.iter().rev() {
+                        atoms.remove(g);
+                    }
+                    dst[calc.index] = self.potential.energy(&atoms);
+                }
+                None => failed.push(calc.index),
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(failed)
+        }
+    }
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Return an orthonormal basis for the rigid translations and rotations of
+/// the atoms with flattened coordinates `x`.
+fn rigid_basis(x: &[f64]) -> Vec<Vec<f64>> {
+    let natoms = x.len() / 3;
+    let mut c = [0.0; 3];
+    for atom in x.chunks(3) {
+        for (ck, ak) in c.iter_mut().zip(atom) {
+            *ck += ak / natoms as f64;
+        }
+    }
+    let mut vecs = Vec::new();
+    for k in 0..3 {
+        let mut v = vec![0.0; x.len()];
+        for i in 0..natoms {
+            v[3 * i + k] = 1.0;
+        }
+        vecs.push(v);
+    }
+    for k in 0..3 {
+        let mut v = vec![0.0; x.len()];
+        for (i, atom) in x.chunks(3).enumerate() {
+            let r = [atom[0] - c[0], atom[1] - c[1], atom[2] - c[2]];
+            // the cross product of the unit vector along k with r
+            let (a, b) = ((k + 1) % 3, (k + 2) % 3);
+            v[3 * i + b] = r[a];
+            v[3 * i + a] = -r[b];
+        }
+        vecs.push(v);
+    }
+    // Gram-Schmidt, dropping the missing rotation of a linear molecule
+    let mut basis: Vec<Vec<f64>> = Vec::new();
+    for mut v in vecs {
+        for b in &basis {
+            let d = dot(&v, b);
+            for (vi, bi) in v.iter_mut().zip(b) {
+                *vi -= d * bi;
+            }
+        }
+        let norm = dot(&v, &v).sqrt();
+        if norm > 1e-8 {
+            v.iter_mut().for_each(|vi| *vi /= norm);
+            basis.push(v);
+        }
+    }
+    basis
+}
+
+/// Update the inverse Hessian approximation `h` with the step `s` and the
+/// change in gradient `y`, where `sy` is their dot product.
+fn bfgs_update(h: &mut [f64], s: &[f64], y: &[f64], sy: f64) {
+    let n = s.len();
+    let rho = 1.0 / sy;
+    let hy: Vec<_> = h.chunks(n).map(|row| dot(row, y)).collect();
+    let yhy = dot(y, &hy);
+    for (i, row) in h.chunks_mut(n).enumerate() {
+        for (j, hij) in row.iter_mut().enumerate() {
+            *hij += -rho * (hy[i] * s[j] + s[i] * hy[j])
+                + (rho * rho * yhy + rho) * s[i] * s[j];
+        }
+    }
+}
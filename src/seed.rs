@@ -0,0 +1,250 @@
+//! Starting geometries built from the results of neighbouring grid points
+
+use std::collections::HashMap;
+
+use symm::Atom;
+
+use crate::align::{position, Alignment};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_zmat() {
+        let template = "O
+H 1 oh
+X 2 1.0 1 90.0
+He 2 {{r}} 3 90.0 1 {{theta}}
+
+OH=                  0.96404013
+";
+        let host =
+            vec![Atom::new(8, 0.0, 0.0, 0.0), Atom::new(1, 0.0, 0.0, 0.97)];
+        let got = seed_host(template, &host).unwrap();
+        let want = "O
+H 1 oh
+X 2 1.0 1 90.0
+He 2 {{r}} 3 90.0 1 {{theta}}
+
+OH= 0.9700000000
+";
+        assert_eq!(got, want);
+        assert!(seed_host(template, &host[..1]).is_none());
+    }
+
+    #[test]
+    fn seed_literal_angle() {
+        let template = "O\nH 1 0.96\nH 1 0.96 2 104.5\nHe {{x}} {{y}} {{z}}";
+        let host = vec![
+            Atom::new(8, 0.0, 0.0, 0.0),
+            Atom::new(1, 1.0, 0.0, 0.0),
+            Atom::new(1, 0.0, 1.0, 0.0),
+        ];
+        let got = seed_host(template, &host).unwrap();
+        let want = "O\nH 1 1.0000000000\nH 1 1.0000000000 2 90.0000000000\n\
+                    He {{x}} {{y}} {{z}}";
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn seed_cartesian() {
+        let template = "H 0.0 0.0 -0.9\nO 0.0 0.0 0.06\nHe {{x}} {{y}} {{z}}";
+        // stretched to 0.97 Å, and reoriented along the x-axis
+        let host =
+            vec![Atom::new(1, 0.5, 0.0, 0.0), Atom::new(8, -0.47, 0.0, 0.0)];
+        let got = seed_host(template, &host).unwrap();
+        let lines: Vec<_> = got.lines().collect();
+        let coords = |line: &str| -> Vec<f64> {
+            line.split_whitespace()
+                .skip(1)
+                .map(|f| f.parse().unwrap())
+                .collect()
+        };
+        for (line, want) in lines.iter().zip([-0.905, 0.065]) {
+            let got = coords(line);
+            assert!(got[0].abs() < 1e-8 && got[1].abs() < 1e-8, "{line}");
+            assert!((got[2] - want).abs() < 1e-8, "{line}");
+        }
+        assert_eq!(lines[2], "He {{x}} {{y}} {{z}}");
+    }
+}
+
+/// Split a line of a Z-matrix into its fields, which may be separated by
+/// commas or whitespace.
+fn fields(line: &str) -> Vec<&str> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+/// Whether `symbol` labels a dummy atom, like `X` or `Q1`.
+fn is_dummy(symbol: &str) -> bool {
+    let mut chars = symbol.chars();
+    matches!(chars.next(), Some('X' | 'x' | 'Q' | 'q'))
+        && chars.all(|c| c.is_ascii_digit())
+}
+
+/// Parse `fields` as a Cartesian position, if there are exactly three of them
+/// and they are all numbers.
+fn cartesian(fields: &[&str]) -> Option<[f64; 3]> {
+    match fields {
+        [x, y, z] => Some([x.parse().ok()?, y.parse().ok()?, z.parse().ok()?]),
+        _ => None,
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Return the Z-matrix coordinate of `p` relative to `refs`: the distance to
+/// the first reference in Å, the angle through the first to the second in
+/// degrees, or the dihedral angle through all three in degrees.
+fn internal(p: [f64; 3], refs: &[[f64; 3]]) -> f64 {
+    match *refs {
+        [a] => dot(sub(p, a), sub(p, a)).sqrt(),
+        [a, b] => {
+            let (u, v) = (sub(p, a), sub(b, a));
+            let cos = dot(u, v) / (dot(u, u) * dot(v, v)).sqrt();
+            cos.clamp(-1.0, 1.0).acos().to_degrees()
+        }
+        [a, b, c] => {
+            let axis = sub(b, a);
+            let axis = axis.map(|x| x / dot(axis, axis).sqrt());
+            let (u, w) = (sub(p, a), sub(c, b));
+            let u = sub(u, axis.map(|x| x * dot(u, axis)));
+            let w = sub(w, axis.map(|x| x * dot(w, axis)));
+            dot(cross(axis, u), w).atan2(dot(u, w)).to_degrees()
+        }
+        _ => unreachable!("Z-matrix coordinates have one to three references"),
+    }
+}
+
+/// A line of a Z-matrix that places an atom.
+struct Row<'a> {
+    /// The index of the line in the template.
+    line: usize,
+    fields: Vec<&'a str>,
+    /// The index of the atom in the host, if it is a host atom rather than a
+    /// dummy atom or the probe.
+    host: Option<usize>,
+}
+
+/// Fill in the coordinates of the host atoms in the Z-matrix `template` from
+/// `host`, the optimized host geometry of a neighbouring grid point, leaving
+/// the lines with placeholders for the probe untouched. Cartesian lines get
+/// the positions of `host` superimposed on the template geometry, since the
+/// optimized geometry may have been reoriented. On Z-matrix lines, each
+/// distance, angle, and dihedral, or the variable it refers to, is recomputed
+/// from `host`, except for those involving dummy atoms. Returns `None` if the
+/// host atoms in `template` don't match `host` or it refers to atoms by label
+/// instead of by number.
+pub(crate) fn seed_host(template: &str, host: &[Atom]) -> Option<String> {
+    let lines: Vec<_> = template.lines().collect();
+    let mut rows = Vec::new();
+    let mut nhost = 0;
+    for (line, s) in lines.iter().enumerate() {
+        let fs = fields(s);
+        // variable definitions and blank lines
+        if s.contains('=') || fs.is_empty() {
+            continue;
+        }
+        let host = if s.contains("{{") || is_dummy(fs[0]) {
+            None
+        } else {
+            nhost += 1;
+            Some(nhost - 1)
+        };
+        rows.push(Row { line, fields: fs[1..].to_vec(), host });
+    }
+    if nhost != host.len() {
+        return None;
+    }
+
+    let cart: Vec<_> = rows
+        .iter()
+        .filter_map(|r| Some((position(&host[r.host?]), cartesian(&r.fields)?)))
+        .collect();
+    let alignment = if cart.is_empty() {
+        None
+    } else {
+        let (mobile, target): (Vec<_>, Vec<_>) = cart.into_iter().unzip();
+        Alignment::new(&mobile, &target, &vec![1.0; mobile.len()])
+    };
+
+    // the position of the atom on row `r` of the Z-matrix, counting from 1,
+    // if it is a host atom
+    let pos = |r: &str| -> Option<Option<[f64; 3]>> {
+        let r: usize = r.parse().ok()?;
+        let row = rows.get(r.checked_sub(1)?)?;
+        Some(row.host.map(|h| position(&host[h])))
+    };
+
+    let mut ret: Vec<_> = lines.iter().map(|s| s.to_string()).collect();
+    let mut vars = HashMap::new();
+    for row in &rows {
+        let Some(h) = row.host else {
+            continue;
+        };
+        let p = position(&host[h]);
+        let mut fs: Vec<_> = row.fields.iter().map(|f| f.to_string()).collect();
+        if cartesian(&row.fields).is_some() {
+            let p = alignment.as_ref()?.point(p);
+            fs = p.iter().map(|x| format!("{x:.10}")).collect();
+        } else {
+            let mut refs = Vec::new();
+            for (j, pair) in row.fields.chunks(2).enumerate() {
+                let [r, value] = pair else {
+                    return None;
+                };
+                refs.push(pos(r)?);
+                // the coordinate involves a dummy atom or the probe
+                let Some(refs) =
+                    refs.iter().copied().collect::<Option<Vec<_>>>()
+                else {
+                    continue;
+                };
+                let x = internal(p, &refs);
+                if value.parse::<f64>().is_ok() {
+                    fs[2 * j + 1] = format!("{x:.10}");
+                } else if let Some(name) = value.strip_prefix('-') {
+                    vars.insert(name.to_lowercase(), -x);
+                } else {
+                    vars.insert(value.to_lowercase(), x);
+                }
+            }
+        }
+        if fs != row.fields {
+            let symbol = fields(lines[row.line])[0];
+            ret[row.line] = format!("{symbol} {}", fs.join(" "));
+        }
+    }
+
+    for (line, s) in lines.iter().enumerate() {
+        let Some((name, _)) = s.split_once('=') else {
+            continue;
+        };
+        if let Some(x) = vars.get(&name.trim().to_lowercase()) {
+            ret[line] = format!("{}= {x:.10}", name.trim_end());
+        }
+    }
+
+    let mut ret = ret.join("\n");
+    if template.ends_with('\n') {
+        ret.push('\n');
+    }
+    Some(ret)
+}
@@ -0,0 +1,291 @@
+//! Detection of symmetry-equivalent probe positions
+
+use std::fmt::Display;
+
+use symm::{Atom, Molecule};
+
+use crate::align::position;
+use crate::grid::Point;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// OH⁻ along the z-axis, with the center of mass at the origin
+    fn oh() -> Molecule {
+        Molecule::new(vec![
+            Atom::new(1, 0.0, 0.0, -0.9077),
+            Atom::new(8, 0.0, 0.0, 0.0572),
+        ])
+    }
+
+    /// NH₃ with its C₃ axis along z and one H in the yz-plane
+    fn nh3() -> Molecule {
+        let mut atoms = vec![Atom::new(7, 0.0, 0.0, 0.1)];
+        for angle in [90f64, 210.0, 330.0] {
+            let (s, c) = angle.to_radians().sin_cos();
+            atoms.push(Atom::new(1, 0.94 * c, 0.94 * s, -0.27));
+        }
+        Molecule::new(atoms)
+    }
+
+    #[test]
+    fn linear() {
+        let Symmetry::Linear { axis, inversion, .. } = Symmetry::new(&oh())
+        else {
+            panic!("expected a linear host");
+        };
+        assert!((axis[2].abs() - 1.0).abs() < 1e-12);
+        assert!(!inversion);
+
+        let n2 = Molecule::new(vec![
+            Atom::new(7, 0.0, 0.0, -0.55),
+            Atom::new(7, 0.0, 0.0, 0.55),
+        ]);
+        let got = Symmetry::new(&n2);
+        assert!(matches!(got, Symmetry::Linear { inversion: true, .. }));
+        assert_eq!(got.to_string(), "D∞h");
+    }
+
+    #[test]
+    fn prune_linear() {
+        let mut points = Vec::new();
+        for z in [-0.3, 0.3] {
+            for y in [-0.2, 0.0, 0.2] {
+                points.push(Point::Cartesian { x: 0.0, y, z });
+            }
+        }
+        // any rotation about the axis is a symmetry operation, not just C₂
+        points.push(Point::Cartesian { x: 0.2, y: 0.0, z: -0.3 });
+        let sym = Symmetry::new(&oh());
+        let got = representatives(&points, Some(&sym));
+        assert_eq!(got, vec![0, 1, 0, 3, 4, 3, 0]);
+        let want: Vec<_> = (0..points.len()).collect();
+        assert_eq!(representatives(&points, None), want);
+    }
+
+    #[test]
+    fn prune_c3v() {
+        let sym = Symmetry::new(&nh3());
+        let Symmetry::Finite { ops, .. } = &sym else {
+            panic!("expected a finite point group");
+        };
+        assert_eq!(ops.len(), 6);
+
+        // the first two are related by C₃, which is not one of the sign flips
+        // of the axes, and the last is not related to the third at all, even
+        // though it is its reflection through the xz-plane
+        let points: Vec<_> = [90f64, 210.0, 45.0, 315.0]
+            .into_iter()
+            .map(|angle| {
+                let (s, c) = angle.to_radians().sin_cos();
+                Point::Cartesian { x: c, y: s, z: 1.0 }
+            })
+            .collect();
+        assert_eq!(representatives(&points, Some(&sym)), vec![0, 0, 2, 3]);
+    }
+}
+
+/// Tolerance for comparing Cartesian coordinates in Ångström.
+const TOL: f64 = 1e-4;
+
+/// A rotation or rotation-reflection, as a matrix acting on column vectors.
+type Matrix = [[f64; 3]; 3];
+
+/// The symmetry of a host molecule, described by the operations that map it
+/// onto itself. All of them leave the centroid of the host, `center`, fixed.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Symmetry {
+    /// A single atom, mapped onto itself by any rotation or reflection.
+    Atomic { center: [f64; 3] },
+
+    /// A linear molecule along the unit vector `axis`, mapped onto itself by
+    /// any rotation about the axis and any reflection through a plane
+    /// containing it. If `inversion` is set, it is also mapped onto itself by
+    /// swapping its ends, making its point group D∞h rather than C∞v.
+    Linear {
+        center: [f64; 3],
+        axis: [f64; 3],
+        inversion: bool,
+    },
+
+    /// Any other molecule, with the finite point group whose elements,
+    /// including the identity, are applied about `center` by `ops`.
+    Finite { center: [f64; 3], ops: Vec<Matrix> },
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn norm(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn scale(a: [f64; 3], s: f64) -> [f64; 3] {
+    a.map(|x| x * s)
+}
+
+fn apply(op: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    op.map(|row| dot(row, v))
+}
+
+fn close(a: [f64; 3], b: [f64; 3]) -> bool {
+    norm(sub(a, b)) < TOL
+}
+
+/// Return the centroid of `atoms`.
+fn centroid(atoms: &[Atom]) -> [f64; 3] {
+    let n = atoms.len() as f64;
+    atoms.iter().fold([0.0; 3], |[x, y, z], a| {
+        [x + a.x / n, y + a.y / n, z + a.z / n]
+    })
+}
+
+/// Return an orthonormal frame, as the rows of a matrix, with its first axis
+/// along `u` and its second in the plane of `u` and `v`, which must not be
+/// parallel. The third axis is flipped if `mirror` is set.
+fn frame(u: [f64; 3], v: [f64; 3], mirror: bool) -> Matrix {
+    let e1 = scale(u, 1.0 / norm(u));
+    let e2 = sub(v, scale(e1, dot(v, e1)));
+    let e2 = scale(e2, 1.0 / norm(e2));
+    let e3 = cross(e1, e2);
+    [e1, e2, if mirror { scale(e3, -1.0) } else { e3 }]
+}
+
+impl Symmetry {
+    /// Find the symmetry operations of `host`. Every operation of a finite
+    /// point group is fixed by where it sends two atoms that are not in line
+    /// with the centroid, so the candidates are the proper and improper
+    /// operations sending that pair onto each other pair of atoms of the same
+    /// elements at the same distances and angle, and those that map every
+    /// atom onto an atom of the same element are kept.
+    pub(crate) fn new(host: &Molecule) -> Self {
+        let center = centroid(&host.atoms);
+        let atoms: Vec<_> = host
+            .atoms
+            .iter()
+            .map(|a| (a.atomic_number, sub(position(a), center)))
+            .collect();
+        let Some(&(za, a)) = atoms.iter().find(|(_, r)| norm(*r) > TOL) else {
+            return Self::Atomic { center };
+        };
+        let axis = scale(a, 1.0 / norm(a));
+        let Some(&(zb, b)) =
+            atoms.iter().find(|(_, r)| norm(cross(axis, *r)) > TOL)
+        else {
+            let inversion = atoms.iter().all(|&(z, r)| {
+                atoms
+                    .iter()
+                    .any(|&(w, s)| w == z && close(scale(r, -1.0), s))
+            });
+            return Self::Linear { center, axis, inversion };
+        };
+
+        let maps_onto_itself = |op: &Matrix| {
+            atoms.iter().all(|&(z, r)| {
+                let moved = apply(op, r);
+                atoms.iter().any(|&(w, s)| w == z && close(moved, s))
+            })
+        };
+        let from = frame(a, b, false);
+        let mut ops: Vec<Matrix> = Vec::new();
+        for &(_, c) in atoms.iter().filter(|(z, _)| *z == za) {
+            for &(_, d) in atoms.iter().filter(|(z, _)| *z == zb) {
+                if (norm(c) - norm(a)).abs() > TOL
+                    || (norm(d) - norm(b)).abs() > TOL
+                    || (norm(sub(c, d)) - norm(sub(a, b))).abs() > TOL
+                {
+                    continue;
+                }
+                for mirror in [false, true] {
+                    // sends the axes of `from` onto those of `to`
+                    let to = frame(c, d, mirror);
+                    let op: Matrix = std::array::from_fn(|i| {
+                        std::array::from_fn(|j| {
+                            (0..3).map(|k| to[k][i] * from[k][j]).sum()
+                        })
+                    });
+                    if maps_onto_itself(&op)
+                        && !ops.iter().any(|o| {
+                            o.iter().zip(&op).all(|(r, s)| close(*r, *s))
+                        })
+                    {
+                        ops.push(op);
+                    }
+                }
+            }
+        }
+        Self::Finite { center, ops }
+    }
+
+    /// Report whether the points `p` and `q` are related by one of the
+    /// operations in `self`.
+    fn equivalent(&self, p: [f64; 3], q: [f64; 3]) -> bool {
+        match self {
+            Symmetry::Atomic { center } => {
+                (norm(sub(p, *center)) - norm(sub(q, *center))).abs() < TOL
+            }
+            &Symmetry::Linear { center, axis, inversion } => {
+                let (p, q) = (sub(p, center), sub(q, center));
+                let (sp, sq) = (dot(p, axis), dot(q, axis));
+                let radial = |r, s| norm(sub(r, scale(axis, s)));
+                (radial(p, sp) - radial(q, sq)).abs() < TOL
+                    && ((sp - sq).abs() < TOL
+                        || inversion && (sp + sq).abs() < TOL)
+            }
+            Symmetry::Finite { center, ops } => {
+                let (p, q) = (sub(p, *center), sub(q, *center));
+                ops.iter().any(|op| close(apply(op, p), q))
+            }
+        }
+    }
+}
+
+impl Display for Symmetry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Symmetry::Atomic { .. } => write!(f, "Kh"),
+            Symmetry::Linear { inversion: false, .. } => write!(f, "C∞v"),
+            Symmetry::Linear { inversion: true, .. } => write!(f, "D∞h"),
+            Symmetry::Finite { ops, .. } => {
+                write!(f, "point group of order {}", ops.len())
+            }
+        }
+    }
+}
+
+/// For each of `points`, return the index of the first point in `points`
+/// related to it by `symmetry`. Symmetry-unique points, and all of the points
+/// if there is no `symmetry`, are their own representatives.
+pub(crate) fn representatives(
+    points: &[Point],
+    symmetry: Option<&Symmetry>,
+) -> Vec<usize> {
+    let carts: Vec<_> = points.iter().map(Point::cartesian).collect();
+    carts
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| {
+            let Some(symmetry) = symmetry else {
+                return i;
+            };
+            carts[..i]
+                .iter()
+                .position(|&q| symmetry.equivalent(p, q))
+                .unwrap_or(i)
+        })
+        .collect()
+}